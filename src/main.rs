@@ -1,106 +1,99 @@
 use std::{thread::sleep, time::Duration};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 struct Vec2i16 {
     x: i16,
     y: i16,
 }
 
+#[derive(Copy, Clone)]
 struct Square {
     position: Vec2i16,
     size:     Vec2i16,
 }
 
-const SPACE_CHAR:    u8 = ' '  as u8;
-const NEW_LINE_CHAR: u8 = '\n' as u8;
-const BOX_CHAR:      u8 = 178;
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+enum Color {
+    #[default]
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    // Entry of the 256 color palette
+    Indexed(u8),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+struct Style {
+    fg:        Color,
+    bg:        Color,
+    bold:      bool,
+    underline: bool,
+}
+
+const SPACE_CHAR:    char = ' ';
+const BOX_CHAR:      char = '▓';
+const BLOCK_CHAR:    char = '█';
+const DOT_CHAR:      char = '●';
+const UPPER_HALF_CHAR: char = '▀';
+const LOWER_HALF_CHAR: char = '▄';
 const W_KEY:         u8 = 87;
 const A_KEY:         u8 = 65;
 const D_KEY:         u8 = 68;
 const S_KEY:         u8 = 83;
 const Q_KEY:         u8 = 81;
+const P_KEY:         u8 = 80;
+const R_KEY:         u8 = 82;
+const H_KEY:         u8 = 72;
+const J_KEY:         u8 = 74;
+const K_KEY:         u8 = 75;
+const L_KEY:         u8 = 76;
+const ENTER_KEY:     u8 = 13;
+const BACKSPACE_KEY: u8 = 8;
+const SPACE_KEY:     u8 = 32;
+const LEFT_KEY:      u8 = 37;
+const UP_KEY:        u8 = 38;
+const RIGHT_KEY:     u8 = 39;
+const DOWN_KEY:      u8 = 40;
+// Numpad digits follow on from this one
+const NUMPAD_0_KEY:  u8 = 96;
 
-#[cfg(windows)]
-const STD_OUTPUT:     u32 = -11_i32 as u32;
-#[cfg(windows)]
-const WH_KEYBOARD_LL: i32 = 13;
-#[cfg(windows)]
-struct WinInput {
-    hook_id: winapi::shared::windef::HHOOK,
-}
-
+// Columns a glyph occupies on a terminal, either one or two.
+// Zero width and control characters still take a whole cell here.
+fn glyph_width(c: char) -> usize {
+    use unicode_width::UnicodeWidthChar;
 
-#[cfg(windows)]
-fn get_updated_term_vec2() -> Vec2i16 {
-    use winapi::um::processenv::GetStdHandle;
-    use winapi::um::wincon::GetConsoleScreenBufferInfo;
-    use winapi::um::wincon::CONSOLE_SCREEN_BUFFER_INFO;
-    use winapi::um::wincon::SMALL_RECT;
-    use winapi::um::wincon::COORD;
-    
-    let mut csbi = CONSOLE_SCREEN_BUFFER_INFO {
-        dwSize: COORD { X: (-1), Y: (-1) },
-        dwCursorPosition: COORD { X: (-1), Y: (-1) },
-        wAttributes: -1_i16 as u16,
-        srWindow: SMALL_RECT { Left: (-1), Top: (-1), Right: (-1), Bottom: (-1) },
-        dwMaximumWindowSize: COORD { X: (-1), Y: (-1) },
-    };
-    
-    unsafe {
-        let console_handle = GetStdHandle(STD_OUTPUT);
-        
-        GetConsoleScreenBufferInfo(console_handle, &mut csbi);
-    }
-    
-    let dims = csbi.dwSize;
-    Vec2i16 { x: dims.X, y: dims.Y }
+    c.width().unwrap_or(1).clamp(1, 2)
 }
 
 #[cfg(windows)]
-fn set_term_cursor_pos(dim: Vec2i16) {
-    use winapi::um::processenv::GetStdHandle; 
-    use winapi::um::wincon::SetConsoleCursorPosition;
-    use winapi::um::wincon::COORD;
-
-    unsafe {
-        let console_handle = GetStdHandle(STD_OUTPUT);
-        let coord = COORD { X: dim.x, Y: dim.y };
-    
-        SetConsoleCursorPosition(console_handle, coord);
-    }
-}
-
+const WH_KEYBOARD_LL: i32 = 13;
 #[cfg(windows)]
-fn output_sized_array(arr_ptr: *const u8, arr_size: i16) {
-    use core::ptr::null_mut;
-    use winapi::ctypes::c_void;
-    use winapi::um::consoleapi::WriteConsoleA;
-    use winapi::um::processenv::GetStdHandle;
-
-    unsafe {
-        let console_handle = GetStdHandle(STD_OUTPUT);
-
-        WriteConsoleA(
-            console_handle, 
-            arr_ptr as *const c_void,
-            arr_size as u32,
-            null_mut(),
-            null_mut());
-    }
-}
-
-fn output_array(arr: &Vec<u8>) {
-    output_sized_array(arr.as_ptr(), arr.len() as i16);
-}
-
-fn output_new_line() {
-    static NEW_LINE_STR: [u8; 1] =  [ NEW_LINE_CHAR ];
-    output_sized_array(NEW_LINE_STR.as_ptr(), NEW_LINE_STR.len() as i16);
+struct WinInput {
+    hook_id: winapi::shared::windef::HHOOK,
 }
 
-fn output_string(msg : &str) {
-    output_sized_array(msg.as_ptr(), msg.len() as i16);
-    output_new_line();
+#[cfg(unix)]
+const STD_INPUT:      i32 = libc::STDIN_FILENO;
+#[cfg(unix)]
+const ESCAPE_CHAR:    u8 = 0x1b;
+#[cfg(unix)]
+struct UnixInput {
+    original: libc::termios,
 }
 
 #[cfg(windows)]
@@ -119,7 +112,10 @@ fn set_up_keyboard_hook_on_this_thread() -> WinInput {
     return r;
 }
 
-static mut KEY: u32 = 20;
+// Key downs seen by the hook, waiting for the input thread to pick them up
+#[cfg(windows)]
+static KEY_EVENTS: std::sync::Mutex<std::collections::VecDeque<u32>> =
+    std::sync::Mutex::new(std::collections::VecDeque::new());
 
 #[cfg(windows)]
 unsafe extern "system" fn windows_ll_hook(
@@ -133,7 +129,9 @@ unsafe extern "system" fn windows_ll_hook(
     let kbd: &KBDLLHOOKSTRUCT = (l_param as *const KBDLLHOOKSTRUCT).as_ref().unwrap();
     
     if w_param == WM_KEYDOWN as usize {
-        KEY = kbd.vkCode;
+        if let Ok(mut events) = KEY_EVENTS.lock() {
+            events.push_back(kbd.vkCode);
+        }
     }
 
     CallNextHookEx(std::ptr::null_mut(), code, w_param, l_param)
@@ -150,608 +148,5005 @@ fn end_keyboard_hook_on_this_thread(wi: &mut WinInput) {
     wi.hook_id = std::ptr::null_mut();
 }
 
-mod term_steady_out {
-    use std::mem::swap;
-    use std::usize;
-    use crate::get_updated_term_vec2;
-    use crate::set_term_cursor_pos;
-    use crate::output_sized_array;
-    use crate::Vec2i16;
-    use crate::Square;
-    use crate::BOX_CHAR;
-    use crate::SPACE_CHAR;
-    use private::SteadyRender;
+#[cfg(unix)]
+fn set_up_raw_mode_on_stdin() -> Option<UnixInput> {
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
 
-    pub struct Renderer {
-        terminal_dim: Vec2i16,
-        back_buffer: Vec<u8>,
-        front_buffer: Vec<u8>,
-        objects: Vec<*const MashedPixels>,
+    unsafe {
+        // Stdin is not a tty, there is nothing to switch
+        if libc::tcgetattr(STD_INPUT, original.as_mut_ptr()) != 0 {
+            return None;
+        }
     }
 
-    pub struct MashedPixels {
-        pub sqare: Square,
-    }
+    let original = unsafe { original.assume_init() };
+    let mut raw = original;
 
-    pub trait Render: private::SteadyRender {
-        fn paint_whole_screen_in_letter_a(&mut self);
-        fn render(&mut self);
-    }
+    // Non-canonical, no echo, return from read without waiting
+    // for a whole line. Keep ISIG so ctrl+c still interrupts.
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
 
-    mod private {
-        pub trait SteadyRender {
-            fn resize(&mut self);
-            fn paint_whole_screen(&mut self);
-            fn clear_whole_screen(&mut self);
-            fn stamp_obj(&mut self, index: &usize);
-            fn update_objs(&mut self);
-            fn swap_buffers(&mut self);
-            fn steady_render(&mut self);
+    unsafe {
+        if libc::tcsetattr(STD_INPUT, libc::TCSANOW, &raw) != 0 {
+            return None;
         }
     }
 
-    impl Renderer {
-        // Initialize the renderer component,
-        // get the current terminal dimensions, 
-        // create two vectors for back and front buffer,
-        // create a vector for objects that are assoscieted with the component
-        pub fn initialize() -> Self {
-            let mut r = Renderer { 
-                terminal_dim: crate::get_updated_term_vec2(),
-                back_buffer: Vec::<u8>::new(),
-                front_buffer: Vec::<u8>::new(), 
-                objects: Vec::<*const MashedPixels>::new(), 
-            };
+    *STDIN_MODE.lock().unwrap_or_else(|e| e.into_inner()) = Some(original);
 
-            r.clear_whole_screen();
-            r.swap_buffers();
-            r.clear_whole_screen();
+    // Keypad application mode, so the numpad tells itself apart from the digit row
+    write_to_stdout(KEYPAD_APPLICATION_ON);
 
-            return r;
-        }
+    Some(UnixInput { original })
+}
+
+#[cfg(unix)]
+fn end_raw_mode_on_stdin(ui: &UnixInput) {
+    // Already given back by the panic hook
+    if STDIN_MODE.lock().unwrap_or_else(|e| e.into_inner()).take().is_none() {
+        return;
     }
 
-    impl MashedPixels {
-        // Create new object in passed renderer
-        // return mutable reference for the object,
-        // set it as updated for it to be rendered
-        pub fn initialize(& self, output: &mut Renderer) {
-            let ptr = (self as *const MashedPixels).clone();
-            output.objects.push(ptr);
-        }
+    write_to_stdout(KEYPAD_APPLICATION_OFF);
 
-        pub fn set_pos(&mut self, pos: Vec2i16) {
-            self.sqare.position = pos;
-        }
+    unsafe {
+        libc::tcsetattr(STD_INPUT, libc::TCSANOW, &ui.original);
+    }
+}
 
-        pub fn get_pos(&self) -> &Vec2i16 {
-            &self.sqare.position
-        }
+// Stdin settings from before raw mode, kept where the panic hook
+// can reach them while raw mode is on
+#[cfg(unix)]
+static STDIN_MODE: std::sync::Mutex<Option<libc::termios>> = std::sync::Mutex::new(None);
 
-        pub fn get_size(&self) -> &Vec2i16 {
-            &self.sqare.size
+// Give stdin its settings back from the panic hook, a lock held by
+// the panicking thread is not waited on
+#[cfg(unix)]
+fn restore_stdin_mode() {
+    let original = match STDIN_MODE.try_lock() {
+        Ok(mut mode) => mode.take(),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().take(),
+        Err(std::sync::TryLockError::WouldBlock) => None,
+    };
+
+    if let Some(original) = original {
+        write_to_stdout(KEYPAD_APPLICATION_OFF);
+
+        unsafe {
+            libc::tcsetattr(STD_INPUT, libc::TCSANOW, &original);
         }
     }
+}
 
-    impl Render for Renderer {
-        fn render(&mut self) {
-            self.resize();
-            self.clear_whole_screen();
-            self.update_objs();
-            self.swap_buffers();
-            self.steady_render();
-        }
+#[cfg(unix)]
+const KEYPAD_APPLICATION_ON:  &[u8] = b"\x1b=";
+#[cfg(unix)]
+const KEYPAD_APPLICATION_OFF: &[u8] = b"\x1b>";
 
-        fn paint_whole_screen_in_letter_a(&mut self) {
-            self.paint_whole_screen();
-        }
+#[cfg(unix)]
+fn write_to_stdout(bytes: &[u8]) {
+    unsafe {
+        libc::write(libc::STDOUT_FILENO, bytes.as_ptr() as *const libc::c_void, bytes.len());
     }
+}
 
-    impl private::SteadyRender for Renderer  {
-        fn resize(&mut self) {
-            self.terminal_dim = get_updated_term_vec2();
-            let d = (self.terminal_dim.x * self.terminal_dim.y) as usize;
+// Longest wait for the rest of an escape sequence,
+// an escape with nothing after it by then is the escape key
+#[cfg(unix)]
+const ESCAPE_TIMEOUT_MS: i32 = 50;
 
-            if (d != self.back_buffer.len()) || (d != self.front_buffer.len()) {
-                self.back_buffer.resize(d, SPACE_CHAR);
-                self.front_buffer.resize(d, 0);
-                self.clear_whole_screen();
-                self.swap_buffers();
-                self.clear_whole_screen();
-                self.paint_whole_screen();
-            }
-        }
+// Wait up to timeout_ms for stdin to become readable,
+// then return the key codes decoded from what arrived, in order.
+// Pending holds the start of an escape sequence from an earlier read.
+#[cfg(unix)]
+fn read_keys_from_stdin(pending: &mut Vec<u8>, timeout_ms: i32) -> Vec<u32> {
+    let mut fds = libc::pollfd {
+        fd: STD_INPUT,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = if pending.is_empty() { timeout_ms } else { ESCAPE_TIMEOUT_MS };
 
-        fn paint_whole_screen(&mut self) {
-            let d = &self.terminal_dim;
-            
-            set_term_cursor_pos(Vec2i16 { x: 0, y: 0 });
-            crate::output_sized_array(
-                self.front_buffer.as_ptr(), 
-                self.front_buffer.len() as i16 - d.x);
-        }
-        
-        fn clear_whole_screen(&mut self) {
-            self.back_buffer.fill(SPACE_CHAR);
-        }
+    let ready = unsafe { libc::poll(&mut fds, 1, timeout_ms) };
+    if ready <= 0 || (fds.revents & libc::POLLIN) == 0 {
+        return decode_lone_escape(pending);
+    }
 
-        fn stamp_obj(&mut self, index: &usize) {
-            let pixels = &mut unsafe { self.objects[*index].as_ref().unwrap() };
-    
-            for i in pixels.sqare.position.y
-                ..(pixels.sqare.position.y + pixels.sqare.size.y) {
-                let c_y = i as i32 * self.terminal_dim.x as i32;
-
-                for k in pixels.sqare.position.x
-                    ..(pixels.sqare.position.x + pixels.sqare.size.x) {
-                    if (c_y + k as i32) as usize >= self.back_buffer.len() &&
-                        (c_y + k as i32) < 0 {
-                         return;
+    let mut buf = [0_u8; 32];
+    let n = unsafe {
+        libc::read(STD_INPUT, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+    };
+    if n <= 0 {
+        return Vec::new();
+    }
+
+    decode_keys(pending, &buf[..n as usize])
+}
+
+// Map raw tty bytes onto the same codes the windows hook reports,
+// letters and digits become their upper case ascii value (vk codes),
+// carriage return and line feed both become enter, delete and
+// backspace both become backspace.
+// Arrows and the numpad arrive as escape sequences and become their
+// vk codes too, other sequences are skipped so their final byte
+// is not taken for a letter. A sequence cut off at the end of the
+// bytes is left in pending for the next read to finish.
+#[cfg(unix)]
+fn decode_keys(pending: &mut Vec<u8>, bytes: &[u8]) -> Vec<u32> {
+    pending.extend_from_slice(bytes);
+    let bytes = std::mem::take(pending);
+    let mut keys = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+
+        if b == ESCAPE_CHAR {
+            let start = i - 1;
+
+            match bytes.get(i) {
+                Some(b'[') | Some(b'O') => {
+                    let ss3 = bytes[i] == b'O';
+                    i += 1;
+                    while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                        i += 1;
                     }
-                    self.back_buffer[(c_y + k as i32) as usize] = BOX_CHAR;
+                    if i == bytes.len() {
+                        pending.extend_from_slice(&bytes[start..]);
+                        break;
+                    }
+                    if let Some(key) = decode_sequence(bytes[i], ss3) {
+                        keys.push(key as u32);
+                    }
+                    i += 1;
                 }
+                // Vk code of the escape key is the escape byte itself
+                Some(_) => keys.push(ESCAPE_CHAR as u32),
+                None => pending.push(ESCAPE_CHAR),
             }
+            continue;
         }
 
-        fn update_objs(&mut self) {
-            for i in 0..self.objects.len() {
-                self.stamp_obj(&i);
+        if b.is_ascii_alphanumeric() {
+            keys.push(b.to_ascii_uppercase() as u32);
+        }
+        if b == b'\r' || b == b'\n' {
+            keys.push(ENTER_KEY as u32);
+        }
+        if b == 0x7f || b == 0x08 {
+            keys.push(BACKSPACE_KEY as u32);
+        }
+        if b == b' ' {
+            keys.push(SPACE_KEY as u32);
+        }
+    }
+
+    keys
+}
+
+// Nothing more arrived in time, an escape waiting on its own was
+// the escape key, the start of a longer sequence is dropped
+#[cfg(unix)]
+fn decode_lone_escape(pending: &mut Vec<u8>) -> Vec<u32> {
+    match pending.drain(..).as_slice() {
+        [ESCAPE_CHAR] => vec![ESCAPE_CHAR as u32],
+        _ => Vec::new(),
+    }
+}
+
+// Key behind the final byte of an escape sequence. Arrows end
+// in A to D after either "ESC [" or "ESC O", the numpad in keypad
+// application mode sends "ESC O" and p to y for its digits.
+#[cfg(unix)]
+fn decode_sequence(last: u8, ss3: bool) -> Option<u8> {
+    match last {
+        b'A' => Some(UP_KEY),
+        b'B' => Some(DOWN_KEY),
+        b'C' => Some(RIGHT_KEY),
+        b'D' => Some(LEFT_KEY),
+        b'M' if ss3 => Some(ENTER_KEY),
+        b'p'..=b'y' if ss3 => Some(NUMPAD_0_KEY + (last - b'p')),
+        _ => None,
+    }
+}
+
+mod term_backend {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::{Color, Style, Vec2i16};
+
+    // Everything the renderer needs from an output target,
+    // implemented once per platform (or per fake screen)
+    pub trait TerminalBackend {
+        fn get_size(&mut self) -> Vec2i16;
+        fn set_cursor_pos(&mut self, pos: Vec2i16);
+        // Applies to everything written afterwards
+        fn set_style(&mut self, style: &Style);
+        fn write(&mut self, bytes: &[u8]);
+        // Push everything written since the last flush to the screen,
+        // return how many bytes that was
+        fn flush(&mut self) -> usize;
+
+        // Move from a known (or unknown) position to another one,
+        // backends that can do better than an absolute move override this
+        fn move_cursor(&mut self, from: Option<Vec2i16>, to: Vec2i16) {
+            if from != Some(to) {
+                self.set_cursor_pos(to);
             }
         }
 
-        fn swap_buffers(&mut self) {
-            swap(&mut self.back_buffer, &mut self.front_buffer);
+        // Draw on a screen of its own, leaving the shell and its
+        // scrollback untouched, backends without one ignore it
+        fn set_alternate_screen(&mut self, _on: bool) {}
+
+        fn set_cursor_visible(&mut self, _visible: bool) {}
+
+        // Whether get_size is worth asking again, backends without
+        // a change notification simply say yes every time
+        fn size_may_have_changed(&mut self) -> bool {
+            true
         }
+    }
 
-        fn steady_render(&mut self) {
-            let d = &self.terminal_dim;
-            
-            set_term_cursor_pos(Vec2i16 { x: 0, y: 0 });
+    // Set while a real terminal shows the alternate screen,
+    // tells the panic hook there is something to undo
+    static ALTERNATE_SCREEN_ACTIVE: AtomicBool = AtomicBool::new(false);
 
-            let mut anchor_point: usize = usize::MAX;
-            for i in 0..self.front_buffer.len() {
-                if  (anchor_point == usize::MAX) && (self.back_buffer[i] != self.front_buffer[i]) {
-                        anchor_point = i;
-                }
-                if (anchor_point != usize::MAX) && (self.back_buffer[i] == self.front_buffer[i]) {
-                        set_term_cursor_pos(Vec2i16{ x: anchor_point as i16 % d.x, y: anchor_point as i16 / d.x });
-                        output_sized_array(&self.front_buffer[anchor_point], (i - anchor_point) as i16);
-                        set_term_cursor_pos(Vec2i16{ x: 0, y: 0 });
+    // Raised by SIGINT/SIGTERM (ctrl+c/close on windows),
+    // the main loop checks it and shuts down the normal way
+    static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    // Raised by SIGWINCH, the window size has to be queried again
+    #[cfg(unix)]
+    static RESIZE_PENDING: AtomicBool = AtomicBool::new(true);
+
+    static INSTALL_HANDLERS: Once = Once::new();
+
+    pub fn termination_requested() -> bool {
+        TERMINATION_REQUESTED.load(Ordering::SeqCst)
+    }
+
+    // Make sure a crash or a kill never leaves the terminal garbled,
+    // safe to call any number of times
+    pub fn install_restore_handlers() {
+        INSTALL_HANDLERS.call_once(|| {
+            let previous = std::panic::take_hook();
 
-                        anchor_point = usize::MAX;
+            // Leave the alternate screen first so the message
+            // ends up where the user can read it
+            std::panic::set_hook(Box::new(move |info| {
+                if ALTERNATE_SCREEN_ACTIVE.swap(false, Ordering::SeqCst) {
+                    restore_terminal();
                 }
-            }
-    
-            if anchor_point != usize::MAX {
-                 crate::output_sized_array(&self.front_buffer[anchor_point], (self.front_buffer.len() - 1 - anchor_point) as i16);
-            }
+                // Unwinding may never get to drop the input
+                #[cfg(unix)]
+                crate::restore_stdin_mode();
+                previous(info);
+            }));
+
+            install_signal_handlers();
+        });
+    }
+
+    #[cfg(unix)]
+    const RESTORE_SEQUENCE: &[u8] = b"\x1b[0m\x1b[?25h\x1b[?1049l\x1b>";
+
+    #[cfg(unix)]
+    fn restore_terminal() {
+        unsafe {
+            libc::write(
+                STD_OUTPUT,
+                RESTORE_SEQUENCE.as_ptr() as *const libc::c_void,
+                RESTORE_SEQUENCE.len());
         }
     }
-}
 
-mod game_logic {
-    use std::sync::atomic::Ordering;
-    use std::usize;
-    use crate::{A_KEY, D_KEY, Q_KEY, S_KEY, W_KEY};
-    use crate::{get_updated_term_vec2, term_steady_out::{MashedPixels, Renderer}, Vec2i16, Square};
-    
-    pub struct Game {
-        pub alive: bool,
-        world: World,
-        tick: u64,
-        main_actor: Sneak,
-        sneak_peaces: Vec<Peace>,
-        apples: Vec<Apple>,
-        last_input: std::sync::Arc<std::sync::atomic::AtomicU32>
+    #[cfg(unix)]
+    extern "C" fn on_termination_signal(_signal: libc::c_int) {
+        TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
     }
 
-    struct World {
-        size: Vec2i16,
-        center: Vec2i16,
-        walls:  Vec<MashedPixels>,
+    #[cfg(unix)]
+    extern "C" fn on_resize_signal(_signal: libc::c_int) {
+        RESIZE_PENDING.store(true, Ordering::SeqCst);
     }
 
-    enum Direction {
-        Up,
-        Right,
-        Down,
-        Left,
+    #[cfg(unix)]
+    fn install_signal_handlers() {
+        let handler = on_termination_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let resize_handler = on_resize_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+            libc::signal(libc::SIGWINCH, resize_handler);
+        }
     }
 
-    struct Apple {
-        pixels: Box<MashedPixels>,
-        alive: bool,
+    #[cfg(windows)]
+    fn restore_terminal() {
+        use winapi::um::processenv::GetStdHandle;
+        use winapi::um::wincon::SetConsoleActiveScreenBuffer;
+
+        unsafe {
+            SetConsoleActiveScreenBuffer(GetStdHandle(STD_OUTPUT));
+        }
     }
 
-    struct Peace {
-        pixels: Box<MashedPixels>,
-        index: usize,
+    #[cfg(windows)]
+    unsafe extern "system" fn on_console_ctrl(_ctrl_type: u32) -> i32 {
+        TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+        1
     }
-    
-    struct Sneak {
-        pixels: Box<MashedPixels>,
-        direction: Direction,
-        collected: i32,
+
+    #[cfg(windows)]
+    fn install_signal_handlers() {
+        use winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_console_ctrl), 1);
+        }
     }
 
-    impl World {
-        pub fn initialize(output: &mut Renderer) -> Self {
-            let mut r = World {
-                size: get_updated_term_vec2(),
-                center: get_updated_term_vec2(),
-                walls:  Vec::<MashedPixels>::new(),
-            };
-            r.size.x = r.size.x - 1;
-            r.size.y = r.size.y - 1;
-
-            r.center.y = r.center.y - 1;
-            let term_dims = &r.center;
-            r.walls.push(MashedPixels {
-                sqare: Square { position: (Vec2i16 { x: 0, y: 0 }), 
-                                size:     (Vec2i16 { x: 1, y: term_dims.y }) }
-            });
-            r.walls.push(MashedPixels {
-                sqare: Square { position: (Vec2i16 { x: term_dims.x - 1, y: 0 }), 
-                                size:     (Vec2i16 { x: 1, y: term_dims.y }) }
-            });
-            r.walls.push(MashedPixels {
-                sqare: Square { position: (Vec2i16 { x: 0, y: 0 }), 
-                                size:     (Vec2i16 { x: term_dims.x, y: 1 }) }
-            });
-            r.walls.push(MashedPixels {
-                sqare: Square { position: (Vec2i16 { x: 0, y: term_dims.y - 1 }), 
-                                size:     (Vec2i16 { x: term_dims.x, y: 1 }) }
-            });
-            
-            for i in &r.walls {
-                i.initialize(output);
-            }
+    #[cfg(windows)]
+    const STD_OUTPUT: u32 = -11_i32 as u32;
+
+    #[cfg(windows)]
+    pub struct WinConsoleBackend {
+        // Screen buffer written to, the alternate one while it is active
+        handle: winapi::um::winnt::HANDLE,
+        written: usize,
+    }
+
+    #[cfg(windows)]
+    impl WinConsoleBackend {
+        pub fn initialize() -> Self {
+            use winapi::um::consoleapi::SetConsoleOutputCP;
+            use winapi::um::processenv::GetStdHandle;
 
-            r.center.x = r.center.x / 2;
-            r.center.y = r.center.y / 2;
+            // Glyphs are written as UTF-8
+            unsafe {
+                SetConsoleOutputCP(65001);
+            }
 
-            return r;
+            WinConsoleBackend { handle: unsafe { GetStdHandle(STD_OUTPUT) }, written: 0 }
         }
     }
 
-    impl Sneak {
-        pub fn initialize(output: &mut Renderer, world: &World) -> Self {
-            let r = Sneak {
-                pixels: Box::new(MashedPixels {
-                    sqare: Square { position: (Vec2i16 { 
-                                                    x: world.center.x,
-                                                    y: world.center.y }), 
-                                    size:     (Vec2i16 { x: 1, y: 1 }) },
-                }),
-                direction: Direction::Up,
-                collected: 0
+    #[cfg(windows)]
+    impl TerminalBackend for WinConsoleBackend {
+        fn get_size(&mut self) -> Vec2i16 {
+            use winapi::um::wincon::GetConsoleScreenBufferInfo;
+            use winapi::um::wincon::CONSOLE_SCREEN_BUFFER_INFO;
+            use winapi::um::wincon::SMALL_RECT;
+            use winapi::um::wincon::COORD;
+
+            let mut csbi = CONSOLE_SCREEN_BUFFER_INFO {
+                dwSize: COORD { X: (-1), Y: (-1) },
+                dwCursorPosition: COORD { X: (-1), Y: (-1) },
+                wAttributes: -1_i16 as u16,
+                srWindow: SMALL_RECT { Left: (-1), Top: (-1), Right: (-1), Bottom: (-1) },
+                dwMaximumWindowSize: COORD { X: (-1), Y: (-1) },
             };
-            
-            r.pixels.initialize(output);
 
-            return r;
-        }
-    }
+            unsafe {
+                let console_handle = self.handle;
 
-    impl Game {
-        pub fn initialize(output: &mut Renderer, input: &crate::term_input::Input) -> Self {
-            let w = World::initialize(output);
-            let ma = Sneak::initialize(output, &w);
-            let mut apples_vec = Vec::<Apple>::new();
-            let mut sneak_vec = Vec::<Peace>::new();
-            for _i in 0..12 {
-                apples_vec.push(Apple {
-                    pixels: Box::new(MashedPixels {
-                        sqare: Square { position: (Vec2i16 { 
-                            x: -1,
-                            y: -1 }), 
-                            size:     (Vec2i16 { x: 1, y: 1 }) },
-                    }),
-                    alive: false,
-                } );
-
-                apples_vec.last().unwrap().pixels.initialize(output);
-            }
-
-            for _i in 0..(w.size.y * w.size.x) {
-                sneak_vec.push(Peace {
-                    pixels: Box::new(MashedPixels {
-                        sqare: Square { position: (Vec2i16 { 
-                            x: -1,
-                            y: -1 }), 
-                            size:     (Vec2i16 { x: 1, y: 1 }) },
-                    }),
-                    index: -1_isize as usize,
-                } );
-
-                sneak_vec.last().unwrap().pixels.initialize(output);
+                GetConsoleScreenBufferInfo(console_handle, &mut csbi);
             }
 
-            Game {
-                alive: true,
-                world: w,
-                tick: 0,
-                main_actor: ma,
-                apples: apples_vec,
-                sneak_peaces: sneak_vec,
-                last_input: input.last.clone(),
-            }
+            let dims = csbi.dwSize;
+            Vec2i16 { x: dims.X, y: dims.Y }
         }
-        
-        pub fn get_score(&mut self) -> i32 {
-            self.main_actor.collected
+
+        fn set_cursor_pos(&mut self, pos: Vec2i16) {
+            use winapi::um::wincon::SetConsoleCursorPosition;
+            use winapi::um::wincon::COORD;
+
+            unsafe {
+                let console_handle = self.handle;
+                let coord = COORD { X: pos.x, Y: pos.y };
+
+                SetConsoleCursorPosition(console_handle, coord);
+            }
         }
 
-        pub fn update(&mut self) {
-            let pos = self.main_actor.pixels.get_pos();
-            let last_pos: Vec2i16 = Vec2i16 {
-                x: pos.x,
-                y: pos.y,
+        fn set_style(&mut self, style: &Style) {
+            use winapi::um::wincon::SetConsoleTextAttribute;
+            use winapi::um::wincon::{FOREGROUND_RED, FOREGROUND_GREEN, FOREGROUND_BLUE, FOREGROUND_INTENSITY};
+            use winapi::um::wincon::COMMON_LVB_UNDERSCORE;
+
+            // Console attributes only know the 16 basic colors,
+            // background bits are the foreground ones shifted by four
+            let rgbi = |c: &Color, default: u16| -> u16 {
+                let (r, g, b, i) = (FOREGROUND_RED, FOREGROUND_GREEN, FOREGROUND_BLUE, FOREGROUND_INTENSITY);
+                match c {
+                    Color::Black => 0,
+                    Color::Red => r,
+                    Color::Green => g,
+                    Color::Yellow => r | g,
+                    Color::Blue => b,
+                    Color::Magenta => r | b,
+                    Color::Cyan => g | b,
+                    Color::White => r | g | b,
+                    Color::BrightBlack => i,
+                    Color::BrightRed => r | i,
+                    Color::BrightGreen => g | i,
+                    Color::BrightYellow => r | g | i,
+                    Color::BrightBlue => b | i,
+                    Color::BrightMagenta => r | b | i,
+                    Color::BrightCyan => g | b | i,
+                    Color::BrightWhite => r | g | b | i,
+                    Color::Default | Color::Indexed(_) => default,
+                }
             };
-            
-            let last_keystroke = self.last_input.load(Ordering::Relaxed);
-            if last_keystroke == W_KEY as u32 {
-                self.main_actor.direction = Direction::Up;
-            }
-            if last_keystroke == D_KEY as u32 {
-                self.main_actor.direction = Direction::Right;
+
+            let mut attributes = rgbi(&style.fg, FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE)
+                | (rgbi(&style.bg, 0) << 4);
+            if style.bold {
+                attributes |= FOREGROUND_INTENSITY;
             }
-            if last_keystroke == S_KEY as u32 {
-                self.main_actor.direction = Direction::Down;
+            if style.underline {
+                attributes |= COMMON_LVB_UNDERSCORE;
             }
-            if last_keystroke == A_KEY as u32 {
-                self.main_actor.direction = Direction::Left;
+
+            unsafe {
+                let console_handle = self.handle;
+
+                SetConsoleTextAttribute(console_handle, attributes);
             }
-            if last_keystroke == Q_KEY as u32 {
-                self.alive = false;
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            use core::ptr::null_mut;
+            use winapi::ctypes::c_void;
+            use winapi::um::consoleapi::WriteConsoleA;
+
+            unsafe {
+                let console_handle = self.handle;
+
+                WriteConsoleA(
+                    console_handle,
+                    bytes.as_ptr() as *const c_void,
+                    bytes.len() as u32,
+                    null_mut(),
+                    null_mut());
             }
 
-            match self.main_actor.direction {
-                Direction::Up => {
-                    self.main_actor.pixels.set_pos(Vec2i16 {
-                        x: pos.x,
-                        y: pos.y - 1 });
+            self.written += bytes.len();
+        }
+
+        // WriteConsoleA is unbuffered, only the count is reset
+        fn flush(&mut self) -> usize {
+            std::mem::take(&mut self.written)
+        }
+
+        // The console has no alternate screen, a second screen buffer
+        // is created and made active instead
+        fn set_alternate_screen(&mut self, on: bool) {
+            use core::ptr::null_mut;
+            use winapi::um::handleapi::CloseHandle;
+            use winapi::um::processenv::GetStdHandle;
+            use winapi::um::wincon::{CreateConsoleScreenBuffer, SetConsoleActiveScreenBuffer};
+            use winapi::um::wincon::CONSOLE_TEXTMODE_BUFFER;
+            use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE, FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+            unsafe {
+                let std_handle = GetStdHandle(STD_OUTPUT);
+
+                if on && self.handle == std_handle {
+                    let alternate = CreateConsoleScreenBuffer(
+                        GENERIC_READ | GENERIC_WRITE,
+                        FILE_SHARE_READ | FILE_SHARE_WRITE,
+                        null_mut(),
+                        CONSOLE_TEXTMODE_BUFFER,
+                        null_mut());
+
+                    if SetConsoleActiveScreenBuffer(alternate) != 0 {
+                        self.handle = alternate;
+                        ALTERNATE_SCREEN_ACTIVE.store(true, Ordering::SeqCst);
+                    }
                 }
-                Direction::Right => {
-                    self.main_actor.pixels.set_pos(Vec2i16 { 
-                        x: pos.x + 1,
-                        y: pos.y });
-                }
-                Direction::Down => {
-                    self.main_actor.pixels.set_pos(Vec2i16 { 
-                        x: pos.x, 
-                        y: pos.y + 1 });
-                }
-                Direction::Left => {
-                    self.main_actor.pixels.set_pos(Vec2i16 { 
-                        x: pos.x - 1, 
-                        y: pos.y });
+                if !on && self.handle != std_handle {
+                    SetConsoleActiveScreenBuffer(std_handle);
+                    CloseHandle(self.handle);
+                    self.handle = std_handle;
+                    ALTERNATE_SCREEN_ACTIVE.store(false, Ordering::SeqCst);
                 }
             }
-        
-            let cur_snake_pos = self.main_actor.pixels.get_pos().clone();
-            if self.tick % 20 == 0 {
-                let mut random_vec = crate::game_logic::Game::random_vec2i16();
+        }
 
-                random_vec.y = (random_vec.y + 1) % self.world.size.y;    
-                random_vec.x = (random_vec.x + 1) % self.world.size.x;    
+        fn set_cursor_visible(&mut self, visible: bool) {
+            use winapi::um::wincon::{GetConsoleCursorInfo, SetConsoleCursorInfo};
+            use winapi::um::wincon::CONSOLE_CURSOR_INFO;
 
-                for i in self.apples.iter_mut() {
-                    if i.alive {
-                        continue;
-                    }
+            let mut info = CONSOLE_CURSOR_INFO { dwSize: 25, bVisible: 1 };
 
-                    i.pixels.set_pos(random_vec);
-                    i.alive = true;
-                    break;
-                }
+            unsafe {
+                GetConsoleCursorInfo(self.handle, &mut info);
+                info.bVisible = visible as i32;
+                SetConsoleCursorInfo(self.handle, &info);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    const STD_OUTPUT: i32 = libc::STDOUT_FILENO;
+
+    // Collects a whole frame of text and escape sequences
+    // and hands it to stdout in one write on flush
+    #[cfg(unix)]
+    pub struct AnsiBackend {
+        out: std::io::Stdout,
+        frame: Vec<u8>,
+    }
+
+    #[cfg(unix)]
+    impl AnsiBackend {
+        pub fn initialize() -> Self {
+            AnsiBackend { out: std::io::stdout(), frame: Vec::<u8>::new() }
+        }
+    }
+
+    // CSI sequence moving the cursor n cells in the direction given
+    // by the final byte, a count of one is implied when left out
+    fn csi_move(n: i16, dir: char) -> String {
+        match n {
+            0 => String::new(),
+            1 => format!("\x1b[{}", dir),
+            _ => format!("\x1b[{}{}", n, dir),
+        }
+    }
+
+    // Shortest sequence getting the cursor from one cell to another,
+    // an absolute move works from anywhere, relative ones and a carriage
+    // return are usually a few bytes cheaper for nearby targets
+    pub fn cheapest_move(from: Option<Vec2i16>, to: Vec2i16) -> String {
+        let absolute = format!("\x1b[{};{}H", to.y as i32 + 1, to.x as i32 + 1);
+        let from = match from {
+            Some(f) => f,
+            None => return absolute,
+        };
+        if from == to {
+            return String::new();
+        }
+
+        let dy = to.y - from.y;
+        let vertical = if dy < 0 { csi_move(-dy, 'A') } else { csi_move(dy, 'B') };
+
+        let dx = to.x - from.x;
+        let horizontal = if dx < 0 { csi_move(-dx, 'D') } else { csi_move(dx, 'C') };
+        let carriage = format!("\r{}", csi_move(to.x, 'C'));
+
+        [absolute, vertical.clone() + &horizontal, vertical + &carriage]
+            .into_iter()
+            .min_by_key(|s| s.len())
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    impl TerminalBackend for AnsiBackend {
+        fn get_size(&mut self) -> Vec2i16 {
+            let mut ws = libc::winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+
+            let result = unsafe {
+                libc::ioctl(STD_OUTPUT, libc::TIOCGWINSZ, &mut ws)
+            };
+
+            // Not a tty (piped or redirected output),
+            // fall back to the classic VT100 dimensions
+            if result != 0 || ws.ws_col == 0 || ws.ws_row == 0 {
+                return Vec2i16 { x: 80, y: 24 };
             }
-            if crate::game_logic::Game::check_is_in_deadly_collison(&self, &cur_snake_pos) {
-                self.alive = false;
+
+            Vec2i16 {
+                x: ws.ws_col.min(i16::MAX as u16) as i16,
+                y: ws.ws_row.min(i16::MAX as u16) as i16,
             }
+        }
 
-            if crate::game_logic::Game::check_is_in_happy_collison(self, &cur_snake_pos) {
-                self.main_actor.collected = self.main_actor.collected + 1;
+        fn set_cursor_pos(&mut self, pos: Vec2i16) {
+            // CSI row ; column H, both are 1-based
+            let seq = format!("\x1b[{};{}H", pos.y as i32 + 1, pos.x as i32 + 1);
+            self.write(seq.as_bytes());
+        }
 
-                for i in 0..(self.main_actor.collected) as usize {
-                    if self.sneak_peaces[i].index == -1_isize as usize {
-                        self.sneak_peaces[i].index = 0;
-                        continue;
-                    }
+        fn set_style(&mut self, style: &Style) {
+            let seq = sgr_sequence(style);
+            self.write(seq.as_bytes());
+        }
 
-                    self.sneak_peaces[i].index = self.sneak_peaces[i].index + 1;
-                    
-                    if self.sneak_peaces[i].index == (self.main_actor.collected - 1) as usize {
-                        self.sneak_peaces[i].pixels.set_pos(last_pos);
-                    }
-                }
+        fn write(&mut self, bytes: &[u8]) {
+            self.frame.extend_from_slice(bytes);
+        }
+
+        fn flush(&mut self) -> usize {
+            use std::io::Write;
+
+            let mut out = self.out.lock();
+            let _ = out.write_all(&self.frame);
+            let _ = out.flush();
+
+            let n = self.frame.len();
+            self.frame.clear();
+            n
+        }
+
+        fn move_cursor(&mut self, from: Option<Vec2i16>, to: Vec2i16) {
+            let seq = cheapest_move(from, to);
+            self.write(seq.as_bytes());
+        }
+
+        // Entering or leaving twice would save or restore the cursor
+        // twice, so only a change is sent. The panic hook may have left
+        // the alternate screen already.
+        fn set_alternate_screen(&mut self, on: bool) {
+            if ALTERNATE_SCREEN_ACTIVE.swap(on, Ordering::SeqCst) == on {
+                return;
             }
-            else if self.main_actor.collected == 1 {
-                self.sneak_peaces[0].pixels.set_pos(last_pos);
+            if on {
+                self.write(b"\x1b[?1049h");
+            } else {
+                self.write(b"\x1b[0m\x1b[?1049l");
             }
-            else if self.main_actor.collected > 0 {
-                for i in 0..(self.main_actor.collected) as usize {
-                    if self.sneak_peaces[i].index == (self.main_actor.collected - 1) as usize {
-                        self.sneak_peaces[i].index = 0;
-                        continue;
+        }
+
+        fn set_cursor_visible(&mut self, visible: bool) {
+            if visible {
+                self.write(b"\x1b[?25h");
+            } else {
+                self.write(b"\x1b[?25l");
+            }
+        }
+
+        fn size_may_have_changed(&mut self) -> bool {
+            RESIZE_PENDING.swap(false, Ordering::SeqCst)
+        }
+    }
+
+    // Character grid of a fixed size that interprets cursor moves,
+    // plain text and the CSI sequences a real terminal would,
+    // so a frame can be read back as text without a console
+    pub struct VirtualScreen {
+        size: Vec2i16,
+        cursor: Vec2i16,
+        pen: Style,
+        cells: Vec<char>,
+        styles: Vec<Style>,
+        pending: Vec<u8>,
+    }
+
+    // Marks the right half of a double width glyph in a VirtualScreen
+    const WIDE_TAIL_CHAR: char = '\0';
+
+    // Backend writing into a VirtualScreen,
+    // the screen is shared so it can be inspected after
+    // the backend was handed over to the renderer
+    pub struct VirtualBackend {
+        screen: Rc<RefCell<VirtualScreen>>,
+        written: usize,
+    }
+
+    impl VirtualScreen {
+        pub fn initialize(size: Vec2i16) -> Self {
+            let area = (size.x.max(0) as usize) * (size.y.max(0) as usize);
+
+            VirtualScreen {
+                size,
+                cursor: Vec2i16 { x: 0, y: 0 },
+                pen: Style::default(),
+                cells: vec![' '; area],
+                styles: vec![Style::default(); area],
+                pending: Vec::<u8>::new(),
+            }
+        }
+
+        pub fn get_size(&self) -> &Vec2i16 {
+            &self.size
+        }
+
+        pub fn set_style(&mut self, style: &Style) {
+            self.pen = *style;
+        }
+
+        pub fn set_cursor_pos(&mut self, pos: Vec2i16) {
+            self.cursor = Vec2i16 {
+                x: pos.x.clamp(0, (self.size.x - 1).max(0)),
+                y: pos.y.clamp(0, (self.size.y - 1).max(0)),
+            };
+        }
+
+        pub fn write(&mut self, bytes: &[u8]) {
+            self.pending.extend_from_slice(bytes);
+
+            let mut i = 0;
+            while i < self.pending.len() {
+                let consumed = match self.pending[i] {
+                    0x1b => self.apply_escape(i),
+                    b'\n' => {
+                        self.line_feed();
+                        Some(1)
                     }
-                    if self.sneak_peaces[i].index == (self.main_actor.collected - 2) as usize {
-                        self.sneak_peaces[i].pixels.set_pos(last_pos);
+                    b'\r' => {
+                        self.cursor.x = 0;
+                        Some(1)
                     }
+                    _ => self.apply_char(i),
+                };
 
-                    self.sneak_peaces[i].index = self.sneak_peaces[i].index + 1;
+                match consumed {
+                    Some(n) => i += n,
+                    // Sequence split between two writes, wait for the rest
+                    None => break,
                 }
             }
 
-            self.tick = self.tick + 1;
-            if self.tick == u64::max_value() {
-                self.tick = 0;
+            self.pending.drain(..i);
+        }
+
+        fn apply_char(&mut self, at: usize) -> Option<usize> {
+            let lead = self.pending[at];
+            let len = match lead {
+                0x00..=0x7f => 1,
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => {
+                    self.put_char(char::REPLACEMENT_CHARACTER);
+                    return Some(1);
+                }
+            };
+
+            if at + len > self.pending.len() {
+                return None;
             }
+
+            let c = std::str::from_utf8(&self.pending[at..at + len])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.put_char(c);
+
+            Some(len)
         }
 
-        fn random_vec2i16() -> Vec2i16 {
-            Vec2i16 { 
-                x: (crate::game_logic::Game::random_number()),
-                y: (crate::game_logic::Game::random_number()) }
+        fn put_char(&mut self, c: char) {
+            if self.size.x <= 0 || self.size.y <= 0 {
+                return;
+            }
+
+            let w = crate::glyph_width(c) as i16;
+
+            // Auto wrap, the cursor sits past the last column
+            // until the next printable character arrives,
+            // a wide glyph that does not fit wraps as a whole
+            if self.cursor.x + w > self.size.x {
+                self.cursor.x = 0;
+                self.line_feed();
+            }
+
+            // Overwriting half of a wide glyph erases the other half
+            let i = (self.cursor.y as usize) * (self.size.x as usize) + self.cursor.x as usize;
+            let next = i + w as usize;
+            if self.cursor.x > 0 && self.cells[i] == WIDE_TAIL_CHAR {
+                self.cells[i - 1] = ' ';
+            }
+            if self.cursor.x + w < self.size.x && self.cells[next] == WIDE_TAIL_CHAR {
+                self.cells[next] = ' ';
+            }
+
+            self.cells[i] = c;
+            self.styles[i] = self.pen;
+            if w == 2 {
+                self.cells[i + 1] = WIDE_TAIL_CHAR;
+                self.styles[i + 1] = self.pen;
+            }
+            self.cursor.x += w;
         }
 
-        fn random_number() -> i16 {
-            use rand::Rng;
-            rand::thread_rng().gen_range(0..100)
+        fn line_feed(&mut self) {
+            if self.cursor.y + 1 < self.size.y {
+                self.cursor.y += 1;
+                return;
+            }
+
+            // Scroll the whole screen up by one row
+            let w = self.size.x as usize;
+            self.cells.drain(..w);
+            self.cells.extend(std::iter::repeat_n(' ', w));
+            self.styles.drain(..w);
+            self.styles.extend(std::iter::repeat_n(Style::default(), w));
         }
 
-        fn check_is_in_deadly_collison(&self, coord: &Vec2i16) -> bool {
-            for i in self.world.walls.iter() {
-                let wall_pos = i.get_pos();
-                let wall_size = i.get_size();
-                let x_diff = coord.x - wall_pos.x;
-                let y_diff = coord.y - wall_pos.y;
-    
-                if x_diff < 0 || y_diff < 0 {
-                    continue;
+        // Parse ESC [ params final, return bytes consumed
+        fn apply_escape(&mut self, at: usize) -> Option<usize> {
+            let seq = &self.pending[at..];
+            if seq.len() < 2 {
+                return None;
+            }
+            if seq[1] != b'[' {
+                return Some(2);
+            }
+
+            let end = seq[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+            let params: Vec<i16> = std::str::from_utf8(&seq[2..end])
+                .unwrap_or("")
+                .trim_start_matches('?')
+                .split(';')
+                .map(|p| p.parse::<i16>().unwrap_or(0))
+                .collect();
+            let arg = |i: usize, default: i16| -> i16 {
+                match params.get(i) {
+                    Some(&v) if v > 0 => v,
+                    _ => default,
                 }
+            };
 
-                if x_diff < wall_size.x &&
-                    y_diff < wall_size.y {
-                    return true;
+            let c = self.cursor;
+            match seq[end] {
+                b'm' => self.apply_sgr(&params),
+                b'H' | b'f' => self.set_cursor_pos(Vec2i16 { x: arg(1, 1) - 1, y: arg(0, 1) - 1 }),
+                b'A' => self.set_cursor_pos(Vec2i16 { x: c.x, y: c.y - arg(0, 1) }),
+                b'B' => self.set_cursor_pos(Vec2i16 { x: c.x, y: c.y + arg(0, 1) }),
+                b'C' => self.set_cursor_pos(Vec2i16 { x: c.x + arg(0, 1), y: c.y }),
+                b'D' => self.set_cursor_pos(Vec2i16 { x: c.x - arg(0, 1), y: c.y }),
+                b'G' => self.set_cursor_pos(Vec2i16 { x: arg(0, 1) - 1, y: c.y }),
+                b'J' if params.first() == Some(&2) => {
+                    self.cells.fill(' ');
+                    self.styles.fill(self.pen);
                 }
-            }
-            for i in self.sneak_peaces.iter() {
-                let wall_pos = i.pixels.get_pos();
-                let wall_size = i.pixels.get_size();
-                let x_diff = coord.x - wall_pos.x;
-                let y_diff = coord.y - wall_pos.y;
-    
-                if x_diff < 0 || y_diff < 0 {
-                    continue;
+                b'K' => {
+                    let row = (c.y as usize) * (self.size.x as usize);
+                    let from = row + (c.x.min(self.size.x) as usize);
+                    self.cells[from..row + self.size.x as usize].fill(' ');
+                    self.styles[from..row + self.size.x as usize].fill(self.pen);
                 }
+                // Styles, modes and the rest do not change the text
+                _ => {}
+            }
+
+            Some(end + 1)
+        }
+    }
 
-                if x_diff < wall_size.x &&
-                    y_diff < wall_size.y {
-                    return true;
+    // Color of an SGR parameter, relative to 30/40 (or 90/100 for bright)
+    fn sgr_basic_color(n: i16, bright: bool) -> Color {
+        match (n, bright) {
+            (0, false) => Color::Black,
+            (1, false) => Color::Red,
+            (2, false) => Color::Green,
+            (3, false) => Color::Yellow,
+            (4, false) => Color::Blue,
+            (5, false) => Color::Magenta,
+            (6, false) => Color::Cyan,
+            (7, false) => Color::White,
+            (0, true) => Color::BrightBlack,
+            (1, true) => Color::BrightRed,
+            (2, true) => Color::BrightGreen,
+            (3, true) => Color::BrightYellow,
+            (4, true) => Color::BrightBlue,
+            (5, true) => Color::BrightMagenta,
+            (6, true) => Color::BrightCyan,
+            (7, true) => Color::BrightWhite,
+            _ => Color::Default,
+        }
+    }
+
+    impl VirtualScreen {
+        fn apply_sgr(&mut self, params: &[i16]) {
+            let mut i = 0;
+
+            while i < params.len() {
+                match params[i] {
+                    0 => self.pen = Style::default(),
+                    1 => self.pen.bold = true,
+                    4 => self.pen.underline = true,
+                    22 => self.pen.bold = false,
+                    24 => self.pen.underline = false,
+                    n @ 30..=37 => self.pen.fg = sgr_basic_color(n - 30, false),
+                    n @ 40..=47 => self.pen.bg = sgr_basic_color(n - 40, false),
+                    n @ 90..=97 => self.pen.fg = sgr_basic_color(n - 90, true),
+                    n @ 100..=107 => self.pen.bg = sgr_basic_color(n - 100, true),
+                    39 => self.pen.fg = Color::Default,
+                    49 => self.pen.bg = Color::Default,
+                    n @ (38 | 48) if params.get(i + 1) == Some(&5) => {
+                        let c = Color::Indexed(params.get(i + 2).copied().unwrap_or(0) as u8);
+                        if n == 38 {
+                            self.pen.fg = c;
+                        } else {
+                            self.pen.bg = c;
+                        }
+                        i += 2;
+                    }
+                    _ => {}
                 }
+                i += 1;
             }
-            false
         }
+    }
 
-        fn check_is_in_happy_collison(&mut self, coord: &Vec2i16) -> bool {
-            for i in self.apples.iter_mut() {
-                let apple_pos = i.pixels.get_pos();
-                let apple_size = i.pixels.get_size();
-                let x_diff = coord.x - apple_pos.x;
-                let y_diff = coord.y - apple_pos.y;
-    
-                if x_diff < 0 || y_diff < 0 {
-                    continue;
-                }
+    // Reading the screen back, for tests to check what was drawn
+    #[cfg(test)]
+    impl VirtualScreen {
+        pub fn get_cursor_pos(&self) -> &Vec2i16 {
+            &self.cursor
+        }
+
+        // Change the dimensions like a resized window would,
+        // content that still fits is kept in place
+        pub fn resize(&mut self, size: Vec2i16) {
+            let area = (size.x.max(0) as usize) * (size.y.max(0) as usize);
+            let mut cells = vec![' '; area];
+            let mut styles = vec![Style::default(); area];
 
-                if x_diff < apple_size.x &&
-                    y_diff < apple_size.y {
-                    i.alive = false;
-                    i.pixels.set_pos(Vec2i16 { x: (-1), y: (-1) });
-                    return true;
+            for y in 0..self.size.y.min(size.y) {
+                for x in 0..self.size.x.min(size.x) {
+                    let to = (y as usize) * (size.x as usize) + x as usize;
+                    let from = (y as usize) * (self.size.x as usize) + x as usize;
+                    cells[to] = self.cells[from];
+                    styles[to] = self.styles[from];
                 }
             }
-                
-            false
+
+            self.size = size;
+            self.cells = cells;
+            self.styles = styles;
+            self.set_cursor_pos(self.cursor);
         }
-    }
-}
 
-mod term_input {
-    use std::sync::atomic::Ordering;
-    use std::thread::sleep;
-    use std::time::Duration;
+        pub fn get_char(&self, pos: Vec2i16) -> Option<char> {
+            if pos.x < 0 || pos.y < 0 || pos.x >= self.size.x || pos.y >= self.size.y {
+                return None;
+            }
 
-    use crate::{end_keyboard_hook_on_this_thread, set_up_keyboard_hook_on_this_thread};
+            Some(self.cells[(pos.y as usize) * (self.size.x as usize) + pos.x as usize])
+        }
 
-    pub struct Input {
-        handle: std::thread::JoinHandle<()>,
-        pub last: std::sync::Arc<std::sync::atomic::AtomicU32>,
-        loop_handle: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        pub fn get_style(&self, pos: Vec2i16) -> Option<Style> {
+            if pos.x < 0 || pos.y < 0 || pos.x >= self.size.x || pos.y >= self.size.y {
+                return None;
+            }
+
+            Some(self.styles[(pos.y as usize) * (self.size.x as usize) + pos.x as usize])
+        }
+
+        // Text of a row, the right halves of wide glyphs are left out
+        // so the line reads the same as it looks on screen
+        pub fn get_line(&self, y: i16) -> String {
+            (0..self.size.x)
+                .filter_map(|x| self.get_char(Vec2i16 { x, y }))
+                .filter(|c| *c != WIDE_TAIL_CHAR)
+                .collect()
+        }
+
+        // Whole screen, one line per row, joined by new lines
+        pub fn get_text(&self) -> String {
+            (0..self.size.y)
+                .map(|y| self.get_line(y))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
     }
 
-    impl Input {
-        pub fn initialize() -> Self {
-            use crate::KEY;
-            use std::thread;
-            use std::sync::Arc;
+    impl VirtualBackend {
+        pub fn initialize(size: Vec2i16) -> Self {
+            VirtualBackend {
+                screen: Rc::new(RefCell::new(VirtualScreen::initialize(size))),
+                written: 0,
+            }
+        }
 
-            let mut r = Input {
-                handle: thread::spawn(|| {}),
-                last: Arc::new(('W' as u32).into()),
-                loop_handle: Arc::new(true.into()),
-            };
-            let loop_handle_arc = r.loop_handle.clone();
-            let last_key_arc = r.last.clone();
+        #[cfg(test)]
+        pub fn get_screen(&self) -> Rc<RefCell<VirtualScreen>> {
+            self.screen.clone()
+        }
+    }
 
-            let h = std::thread::spawn(move || {
-                    use winapi::um::winuser::PeekMessageA;
-                    use winapi::um::winuser::MSG;
-                    use winapi::um::winuser::PM_REMOVE;
-                    use winapi::shared::windef::HWND;
-                    use winapi::shared::windef::POINT;
-                    
-                    let mut llkbd_hook_id = set_up_keyboard_hook_on_this_thread();
-                    
-                    let mut msg = MSG {
-                        hwnd: 0 as HWND,
-                        message: 0 as u32,
-                        wParam: 0 as usize,
-                        lParam: 0 as isize,
-                        time: 0,
-                        pt: POINT { x: 0, y: 0 },
-                    };
-                    loop {
-                        unsafe {
-                            PeekMessageA(&mut msg, 0 as HWND, 0, 0, PM_REMOVE); 
-                        }
+    impl TerminalBackend for VirtualBackend {
+        fn get_size(&mut self) -> Vec2i16 {
+            *self.screen.borrow().get_size()
+        }
 
-                        if !loop_handle_arc.load(Ordering::Relaxed) {
-                            break;
-                        }
-                        
-                        unsafe { last_key_arc.store(KEY, Ordering::Relaxed); }
-                        sleep(Duration::from_millis(10));
-                    }
-                    
-                    end_keyboard_hook_on_this_thread(&mut llkbd_hook_id);
-                });
-        
-            r.handle = h;
-            return r;
+        fn set_cursor_pos(&mut self, pos: Vec2i16) {
+            self.screen.borrow_mut().set_cursor_pos(pos);
         }
 
-        pub fn destroy(&mut self) {
-            self.loop_handle.store(false, Ordering::Relaxed);
+        fn set_style(&mut self, style: &Style) {
+            self.screen.borrow_mut().set_style(style);
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.screen.borrow_mut().write(bytes);
+            self.written += bytes.len();
+        }
+
+        fn flush(&mut self) -> usize {
+            std::mem::take(&mut self.written)
+        }
+
+        // Same sequences the ANSI backend would send,
+        // so frames cost the same number of bytes here
+        fn move_cursor(&mut self, from: Option<Vec2i16>, to: Vec2i16) {
+            let seq = cheapest_move(from, to);
+            self.write(seq.as_bytes());
+        }
+    }
+
+    // SGR parameter of a color, base is 30 for foreground and 40 for background
+    fn sgr_color(c: &Color, base: u8) -> String {
+        match c {
+            Color::Default => (base + 9).to_string(),
+            Color::Black => base.to_string(),
+            Color::Red => (base + 1).to_string(),
+            Color::Green => (base + 2).to_string(),
+            Color::Yellow => (base + 3).to_string(),
+            Color::Blue => (base + 4).to_string(),
+            Color::Magenta => (base + 5).to_string(),
+            Color::Cyan => (base + 6).to_string(),
+            Color::White => (base + 7).to_string(),
+            Color::BrightBlack => (base + 60).to_string(),
+            Color::BrightRed => (base + 61).to_string(),
+            Color::BrightGreen => (base + 62).to_string(),
+            Color::BrightYellow => (base + 63).to_string(),
+            Color::BrightBlue => (base + 64).to_string(),
+            Color::BrightMagenta => (base + 65).to_string(),
+            Color::BrightCyan => (base + 66).to_string(),
+            Color::BrightWhite => (base + 67).to_string(),
+            Color::Indexed(n) => format!("{};5;{}", base + 8, n),
+        }
+    }
+
+    // Full SGR sequence for a style, starts with a reset
+    // so nothing from the previous style leaks into it
+    pub fn sgr_sequence(style: &Style) -> String {
+        let mut seq = String::from("\x1b[0");
+
+        if style.bold {
+            seq.push_str(";1");
         }
+        if style.underline {
+            seq.push_str(";4");
+        }
+        if style.fg != Color::Default {
+            seq.push(';');
+            seq.push_str(&sgr_color(&style.fg, 30));
+        }
+        if style.bg != Color::Default {
+            seq.push(';');
+            seq.push_str(&sgr_color(&style.bg, 40));
+        }
+
+        seq.push('m');
+        seq
+    }
+
+    // Backend that talks to the terminal this process runs in
+    pub fn default_backend() -> Box<dyn TerminalBackend> {
+        #[cfg(windows)]
+        return Box::new(WinConsoleBackend::initialize());
+
+        #[cfg(unix)]
+        return Box::new(AnsiBackend::initialize());
     }
 }
 
-fn main() {
-    use term_steady_out::Renderer;
-    use term_steady_out::Render;
-    use game_logic::Game;
-    use term_input::Input;
+mod term_steady_out {
+    use std::mem::swap;
+    use crate::term_backend::TerminalBackend;
+    use crate::Vec2i16;
+    use crate::Square;
+    use crate::Style;
+    use crate::Color;
+    use crate::SPACE_CHAR;
+    use crate::{BOX_CHAR, BLOCK_CHAR, UPPER_HALF_CHAR, LOWER_HALF_CHAR};
+    use crate::glyph_width;
+    use private::SteadyRender;
 
-    let mut x = Renderer::initialize();
-    let mut i = Input::initialize();
-    let mut g = Game::initialize(&mut x, &i);
+    // How objects map onto the terminal
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum PixelMode {
+        // One object pixel is one terminal cell drawn with its glyph
+        Cell,
+        // Every cell holds two pixels stacked vertically, drawn as
+        // half blocks in the object's foreground color, cells are about
+        // twice as tall as wide so the pixels come out square
+        HalfBlock,
+    }
 
-    loop
-    {
-        sleep(Duration::from_millis(100));
-        
-        g.update();
-        x.render();
+    pub struct Renderer {
+        backend: Box<dyn TerminalBackend>,
+        terminal_dim: Vec2i16,
+        // Size of the grid objects are positioned in
+        pixel_dim: Vec2i16,
+        pixel_mode: PixelMode,
+        back_buffer: Vec<Cell>,
+        front_buffer: Vec<Cell>,
+        objects: Registry<MashedPixels>,
+        texts: Registry<Text>,
+        // Style the terminal is currently set to, None when unknown
+        pen: Option<Style>,
+        // Where the terminal cursor is, None when unknown
+        cursor: Option<Vec2i16>,
+        // Bytes the last frame took to output
+        frame_bytes: usize,
+        destroyed: bool,
+        // Offset added to every object position
+        origin: Vec2i16,
+        // Fixed size area objects live in, drawn centered with a frame
+        board: Option<Vec2i16>,
+        frame_style: Style,
+        // Shown centered in place of all objects while set
+        notice: Option<String>,
+        // Status line kept on the row right above the board
+        hud: Option<(String, Style)>,
+    }
 
-        if !g.alive {
-            break;
-        }
+    // Unchanged cells between two changed runs on a row
+    // that are rewritten instead of jumped over
+    const REWRITE_GAP: usize = 4;
+
+    // One character position on the screen
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct Cell {
+        pub glyph: char,
+        pub style: Style,
+    }
+
+    const BLANK_CELL: Cell = Cell {
+        glyph: SPACE_CHAR,
+        style: Style {
+            fg: crate::Color::Default,
+            bg: crate::Color::Default,
+            bold: false,
+            underline: false,
+        },
     };
 
-    i.destroy();
+    // Never equal to anything stamped, forces the cell to be repainted
+    const INVALID_CELL: Cell = Cell { glyph: '\u{ffff}', ..BLANK_CELL };
 
-    println!("score {}", g.get_score());
-}
+    // Right half of a double width glyph, the terminal fills it
+    // when the glyph to its left is written, so it is never output
+    const WIDE_TAIL_CHAR: char = '\0';
+
+    pub struct MashedPixels {
+        pub sqare: Square,
+        pub glyph: char,
+        pub style: Style,
+    }
+
+    // String drawn from a terminal cell rightwards, in cells
+    // whatever the pixel mode, cut off where the screen ends
+    pub struct Text {
+        pub position: Vec2i16,
+        pub content: String,
+        pub style: Style,
+    }
+
+    // Reference to an object living in the renderer's registry,
+    // stays valid until the object is removed, after which
+    // it no longer resolves even if its slot gets reused
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct Handle {
+        index: u32,
+        generation: u32,
+    }
+
+    struct Slot<T> {
+        generation: u32,
+        value: Option<T>,
+    }
+
+    // Generational arena owning every object the renderer stamps
+    pub struct Registry<T> {
+        slots: Vec<Slot<T>>,
+        free: Vec<u32>,
+    }
+
+    pub trait Render: private::SteadyRender {
+        // Nothing in the game loop needs it, a frame only rewrites what changed
+        #[allow(dead_code)]
+        fn paint_whole_screen_in_letter_a(&mut self);
+        fn render(&mut self);
+    }
 
+    mod private {
+        use crate::Square;
+        use crate::Style;
+        use crate::Vec2i16;
+        use super::Cell;
+
+        pub trait SteadyRender {
+            fn resize(&mut self) -> bool;
+            fn stamp_notice(&mut self);
+            fn stamp_hud(&mut self);
+            fn stamp_text(&mut self, pos: Vec2i16, text: &str, style: &Style);
+            fn stamp_frame(&mut self);
+            fn paint_whole_screen(&mut self);
+            fn clear_whole_screen(&mut self);
+            fn stamp_obj(&mut self, sqare: &Square, glyph: char, style: &Style);
+            fn put_cell(&mut self, pos: Vec2i16, cell: Cell);
+            fn put_half_block(&mut self, pos: Vec2i16, style: &Style);
+            fn write_cells(&mut self, from: usize, to: usize);
+            fn update_objs(&mut self);
+            fn swap_buffers(&mut self);
+            fn steady_render(&mut self);
+            fn extend_run(&self, from: usize, row_end: usize) -> usize;
+            fn move_cursor_to(&mut self, pos: Vec2i16);
+        }
+    }
+
+    impl Renderer {
+        // Initialize the renderer component on top of the given backend,
+        // get the current terminal dimensions, 
+        // create two vectors for back and front buffer,
+        // create a vector for objects that are assoscieted with the component
+        pub fn initialize(mut backend: Box<dyn TerminalBackend>) -> Self {
+            crate::term_backend::install_restore_handlers();
+
+            backend.set_alternate_screen(true);
+            backend.set_cursor_visible(false);
+            backend.flush();
+
+            let terminal_dim = backend.get_size();
+            let mut r = Renderer { 
+                terminal_dim,
+                pixel_dim: terminal_dim,
+                pixel_mode: PixelMode::Cell,
+                backend,
+                back_buffer: Vec::<Cell>::new(),
+                front_buffer: Vec::<Cell>::new(), 
+                objects: Registry::default(), 
+                texts: Registry::default(),
+                pen: None,
+                cursor: None,
+                frame_bytes: 0,
+                destroyed: false,
+                origin: Vec2i16 { x: 0, y: 0 },
+                board: None,
+                frame_style: Style::default(),
+                notice: None,
+                hud: None,
+            };
+
+            r.clear_whole_screen();
+            r.swap_buffers();
+            r.clear_whole_screen();
+
+            r
+        }
+
+        // Give the terminal back the way it was found,
+        // the renderer draws nothing afterwards
+        pub fn destroy(&mut self) {
+            if self.destroyed {
+                return;
+            }
+
+            self.backend.set_cursor_visible(true);
+            self.backend.set_alternate_screen(false);
+            self.backend.flush();
+            self.destroyed = true;
+        }
+
+        // Pick up a new terminal size, return true if it changed.
+        // Runs on every render as well, calling it earlier lets
+        // the caller react before the next frame is drawn.
+        pub fn update_size(&mut self) -> bool {
+            self.resize()
+        }
+
+        // Give objects a board of their own, positions become board
+        // coordinates and anything outside of it is clipped away
+        pub fn set_board(&mut self, size: Option<Vec2i16>, frame_style: Style) {
+            self.board = size;
+            self.frame_style = frame_style;
+            self.place_board();
+        }
+
+        // Center the board, its frame and the hud line in the pixel grid
+        fn place_board(&mut self) {
+            let d = self.pixel_dim;
+            let hud = self.hud_rows();
+
+            self.origin = match self.board {
+                Some(b) => Vec2i16 {
+                    x: ((d.x - b.x - 2) / 2).max(0) + 1,
+                    y: ((d.y - b.y - 2 - hud) / 2).max(0) + 1 + hud,
+                },
+                None => Vec2i16 { x: 0, y: hud },
+            };
+        }
+
+        // Pixel rows the hud line takes up
+        fn hud_rows(&self) -> i16 {
+            match (&self.hud, self.pixel_mode) {
+                (None, _) => 0,
+                (Some(_), PixelMode::Cell) => 1,
+                (Some(_), PixelMode::HalfBlock) => 2,
+            }
+        }
+
+        // Pixels the board needs, with its frame and the hud line
+        pub fn get_board_extent(&self) -> Option<Vec2i16> {
+            self.board.map(|b| Vec2i16 { x: b.x + 2, y: b.y + 2 + self.hud_rows() })
+        }
+
+        // Same in terminal cells, a half block cell holds two pixel rows
+        pub fn get_board_cells(&self) -> Option<Vec2i16> {
+            self.get_board_extent().map(|e| match self.pixel_mode {
+                PixelMode::Cell => e,
+                PixelMode::HalfBlock => Vec2i16 { x: e.x, y: (e.y + 1) / 2 },
+            })
+        }
+
+        pub fn get_terminal_dim(&self) -> &Vec2i16 {
+            &self.terminal_dim
+        }
+
+        pub fn set_notice(&mut self, notice: Option<String>) {
+            self.notice = notice;
+        }
+
+        // Reserve a line above the board for the text while it is set
+        pub fn set_hud(&mut self, line: Option<String>, style: Style) {
+            let moved = self.hud.is_some() != line.is_some();
+
+            self.hud = line.map(|l| (l, style));
+            if moved {
+                self.place_board();
+            }
+        }
+
+        // Bytes sent to the backend for the last rendered frame
+        pub fn get_frame_bytes(&self) -> usize {
+            self.frame_bytes
+        }
+
+        // Dimensions of the logical pixel grid, in half block mode
+        // there are twice as many rows as the terminal has
+        pub fn get_pixel_dim(&self) -> &Vec2i16 {
+            &self.pixel_dim
+        }
+
+        // Switch the mode and repaint everything on the next frame
+        pub fn set_pixel_mode(&mut self, mode: PixelMode) {
+            self.pixel_mode = mode;
+            self.pixel_dim = Renderer::pixel_dim_of(mode, self.terminal_dim);
+            self.place_board();
+            self.front_buffer.fill(INVALID_CELL);
+        }
+
+        fn pixel_dim_of(mode: PixelMode, terminal_dim: Vec2i16) -> Vec2i16 {
+            match mode {
+                PixelMode::Cell => terminal_dim,
+                PixelMode::HalfBlock => Vec2i16 {
+                    x: terminal_dim.x,
+                    y: terminal_dim.y.saturating_mul(2),
+                },
+            }
+        }
+
+        pub fn objects(&self) -> &Registry<MashedPixels> {
+            &self.objects
+        }
+
+        pub fn objects_mut(&mut self) -> &mut Registry<MashedPixels> {
+            &mut self.objects
+        }
+
+        pub fn texts_mut(&mut self) -> &mut Registry<Text> {
+            &mut self.texts
+        }
+    }
+
+    impl<T> Default for Registry<T> {
+        fn default() -> Self {
+            Registry { slots: Vec::new(), free: Vec::new() }
+        }
+    }
+
+    impl<T> Registry<T> {
+        // Take ownership of the object, reusing a free slot if any
+        pub fn create(&mut self, value: T) -> Handle {
+            if let Some(index) = self.free.pop() {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+
+                return Handle { index, generation: slot.generation };
+            }
+
+            self.slots.push(Slot { generation: 0, value: Some(value) });
+            Handle { index: (self.slots.len() - 1) as u32, generation: 0 }
+        }
+
+        pub fn get(&self, handle: &Handle) -> Option<&T> {
+            self.slots
+                .get(handle.index as usize)
+                .filter(|s| s.generation == handle.generation)
+                .and_then(|s| s.value.as_ref())
+        }
+
+        pub fn get_mut(&mut self, handle: &Handle) -> Option<&mut T> {
+            self.slots
+                .get_mut(handle.index as usize)
+                .filter(|s| s.generation == handle.generation)
+                .and_then(|s| s.value.as_mut())
+        }
+
+        // Drop the object and return it,
+        // bumping the generation invalidates every copy of the handle
+        pub fn remove(&mut self, handle: &Handle) -> Option<T> {
+            let slot = self.slots
+                .get_mut(handle.index as usize)
+                .filter(|s| s.generation == handle.generation)?;
+            let value = slot.value.take()?;
+
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(handle.index);
+
+            Some(value)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (Handle, &T)> {
+            self.slots.iter().enumerate().filter_map(|(i, s)| {
+                s.value.as_ref().map(|v| (Handle { index: i as u32, generation: s.generation }, v))
+            })
+        }
+    }
+
+    impl MashedPixels {
+        // Create new object in passed renderer,
+        // return the handle it can be reached with
+        pub fn initialize(self, output: &mut Renderer) -> Handle {
+            output.objects.create(self)
+        }
+
+        pub fn set_pos(&mut self, pos: Vec2i16) {
+            self.sqare.position = pos;
+        }
+
+        pub fn get_pos(&self) -> &Vec2i16 {
+            &self.sqare.position
+        }
+    }
+
+    impl Text {
+        // Create new text in passed renderer,
+        // return the handle it can be reached with
+        pub fn initialize(self, output: &mut Renderer) -> Handle {
+            output.texts.create(self)
+        }
+    }
+
+    impl Drop for Renderer {
+        fn drop(&mut self) {
+            self.destroy();
+        }
+    }
+
+    impl Render for Renderer {
+        fn render(&mut self) {
+            if self.destroyed {
+                return;
+            }
+
+            // Everything a frame writes, a repaint after a resize
+            // included, goes out in a single flush
+            self.frame_bytes = 0;
+            self.resize();
+            self.clear_whole_screen();
+            self.update_objs();
+            self.swap_buffers();
+            self.steady_render();
+            self.frame_bytes += self.backend.flush();
+        }
+
+        fn paint_whole_screen_in_letter_a(&mut self) {
+            self.paint_whole_screen();
+            self.frame_bytes = self.backend.flush();
+        }
+    }
+
+    impl private::SteadyRender for Renderer  {
+        fn resize(&mut self) -> bool {
+            if !self.backend.size_may_have_changed() && !self.back_buffer.is_empty() {
+                return false;
+            }
+
+            let size = self.backend.get_size();
+            let d = size.x as usize * size.y as usize;
+
+            if size == self.terminal_dim && d == self.back_buffer.len() {
+                return false;
+            }
+
+            self.terminal_dim = size;
+            self.pixel_dim = Renderer::pixel_dim_of(self.pixel_mode, self.terminal_dim);
+            self.place_board();
+
+            // Start over from a blank screen
+            self.back_buffer.clear();
+            self.front_buffer.clear();
+            self.back_buffer.resize(d, BLANK_CELL);
+            self.front_buffer.resize(d, BLANK_CELL);
+            self.paint_whole_screen();
+
+            // The last row is left out of the full paint,
+            // whatever was there gets overwritten on the next frame
+            let last_row = d.saturating_sub(size.x as usize);
+            self.front_buffer[last_row..].fill(INVALID_CELL);
+
+            true
+        }
+
+        fn paint_whole_screen(&mut self) {
+            let d = self.terminal_dim;
+            
+            self.cursor = None;
+            self.move_cursor_to(Vec2i16 { x: 0, y: 0 });
+            self.write_cells(0, self.front_buffer.len() - d.x as usize);
+        }
+        
+        fn clear_whole_screen(&mut self) {
+            self.back_buffer.fill(BLANK_CELL);
+        }
+
+        fn stamp_obj(&mut self, sqare: &Square, glyph: char, style: &Style) {
+            if self.pixel_mode == PixelMode::HalfBlock {
+                let d = self.pixel_dim;
+
+                for i in sqare.position.y.max(0)
+                    ..(sqare.position.y + sqare.size.y).min(d.y) {
+                    for k in sqare.position.x.max(0)
+                        ..(sqare.position.x + sqare.size.x).min(d.x) {
+                        self.put_half_block(Vec2i16 { x: k, y: i }, style);
+                    }
+                }
+
+                return;
+            }
+
+            let d = self.terminal_dim;
+            let w = glyph_width(glyph) as i16;
+            let end_x = (sqare.position.x + sqare.size.x).min(d.x);
+
+            // Clip to the screen, parts hanging off any edge are not drawn,
+            // wide glyphs take two columns so they step by two
+            for i in sqare.position.y.max(0)
+                ..(sqare.position.y + sqare.size.y).min(d.y) {
+                let mut k = sqare.position.x;
+
+                while k + w <= end_x {
+                    if k >= 0 {
+                        self.put_cell(Vec2i16 { x: k, y: i }, Cell { glyph, style: *style });
+                    }
+                    k += w;
+                }
+            }
+        }
+
+        // Place a cell in the back buffer keeping wide glyphs whole,
+        // a half overwritten wide glyph turns into a blank
+        fn put_cell(&mut self, pos: Vec2i16, cell: Cell) {
+            let d = self.terminal_dim;
+            let i = pos.y as usize * d.x as usize + pos.x as usize;
+            let wide = glyph_width(cell.glyph) == 2;
+
+            if wide && pos.x + 1 >= d.x {
+                self.back_buffer[i] = Cell { glyph: SPACE_CHAR, style: cell.style };
+                return;
+            }
+
+            let next_x = pos.x + if wide { 2 } else { 1 };
+            let next = i + (next_x - pos.x) as usize;
+            if pos.x > 0 && self.back_buffer[i].glyph == WIDE_TAIL_CHAR {
+                self.back_buffer[i - 1].glyph = SPACE_CHAR;
+            }
+            if next_x < d.x && self.back_buffer[next].glyph == WIDE_TAIL_CHAR {
+                self.back_buffer[next].glyph = SPACE_CHAR;
+            }
+
+            self.back_buffer[i] = cell;
+            if wide {
+                self.back_buffer[i + 1] = Cell { glyph: WIDE_TAIL_CHAR, style: cell.style };
+            }
+        }
+
+        // Paint one logical pixel, merging it with whatever
+        // already occupies the other half of the same cell
+        fn put_half_block(&mut self, pos: Vec2i16, style: &Style) {
+            let i = (pos.y / 2) as usize * self.terminal_dim.x as usize + pos.x as usize;
+            let cell = self.back_buffer[i];
+
+            let (mut top, mut bottom) = match cell.glyph {
+                UPPER_HALF_CHAR if cell.style.bg != Color::Default => (Some(cell.style.fg), Some(cell.style.bg)),
+                UPPER_HALF_CHAR => (Some(cell.style.fg), None),
+                LOWER_HALF_CHAR => (None, Some(cell.style.fg)),
+                BLOCK_CHAR => (Some(cell.style.fg), Some(cell.style.fg)),
+                _ => (None, None),
+            };
+
+            if pos.y % 2 == 0 {
+                top = Some(style.fg);
+            } else {
+                bottom = Some(style.fg);
+            }
+
+            let base = Style { bold: style.bold, ..Style::default() };
+            self.back_buffer[i] = match (top, bottom) {
+                (Some(t), Some(b)) if t == b => Cell { glyph: BLOCK_CHAR, style: Style { fg: t, ..base } },
+                (Some(t), Some(b)) => Cell { glyph: UPPER_HALF_CHAR, style: Style { fg: t, bg: b, ..base } },
+                (Some(t), None) => Cell { glyph: UPPER_HALF_CHAR, style: Style { fg: t, ..base } },
+                (None, Some(b)) => Cell { glyph: LOWER_HALF_CHAR, style: Style { fg: b, ..base } },
+                (None, None) => BLANK_CELL,
+            };
+        }
+
+        fn update_objs(&mut self) {
+            if self.notice.is_some() {
+                self.stamp_notice();
+                return;
+            }
+
+            let objects = std::mem::take(&mut self.objects);
+            let origin = self.origin;
+            let board = self.board.unwrap_or(self.pixel_dim);
+
+            if self.board.is_some() {
+                self.stamp_frame();
+            }
+
+            for (_, pixels) in objects.iter() {
+                // Clip to the board before moving into place
+                let p = pixels.sqare.position;
+                let end_x = p.x.saturating_add(pixels.sqare.size.x).min(board.x);
+                let end_y = p.y.saturating_add(pixels.sqare.size.y).min(board.y);
+                let start = Vec2i16 { x: p.x.max(0), y: p.y.max(0) };
+
+                if end_x <= start.x || end_y <= start.y {
+                    continue;
+                }
+
+                let sqare = Square {
+                    position: Vec2i16 {
+                        x: start.x.saturating_add(origin.x),
+                        y: start.y.saturating_add(origin.y) },
+                    size: Vec2i16 { x: end_x - start.x, y: end_y - start.y },
+                };
+                self.stamp_obj(&sqare, pixels.glyph, &pixels.style);
+            }
+
+            self.objects = objects;
+
+            // Text goes on top of everything
+            let texts = std::mem::take(&mut self.texts);
+            for (_, text) in texts.iter() {
+                self.stamp_text(text.position, &text.content, &text.style);
+            }
+            self.texts = texts;
+
+            self.stamp_hud();
+        }
+
+        // Wall one pixel thick hugging the board on every side
+        fn stamp_frame(&mut self) {
+            let b = match self.board {
+                Some(b) => b,
+                None => return,
+            };
+            let o = Vec2i16 { x: self.origin.x - 1, y: self.origin.y - 1 };
+            let style = self.frame_style;
+            let sides = [
+                Square { position: o, size: Vec2i16 { x: b.x + 2, y: 1 } },
+                Square { position: Vec2i16 { x: o.x, y: o.y + b.y + 1 }, size: Vec2i16 { x: b.x + 2, y: 1 } },
+                Square { position: o, size: Vec2i16 { x: 1, y: b.y + 2 } },
+                Square { position: Vec2i16 { x: o.x + b.x + 1, y: o.y }, size: Vec2i16 { x: 1, y: b.y + 2 } },
+            ];
+
+            for side in sides.iter() {
+                self.stamp_obj(side, BOX_CHAR, &style);
+            }
+        }
+
+        // Notice text in the middle of the screen, clipped to its width
+        fn stamp_notice(&mut self) {
+            let d = self.terminal_dim;
+            let notice = self.notice.clone().unwrap_or_default();
+            let width: i16 = notice.chars().map(|c| glyph_width(c) as i16).sum();
+
+            self.stamp_text(
+                Vec2i16 { x: ((d.x - width) / 2).max(0), y: d.y / 2 },
+                &notice,
+                &Style::default());
+        }
+
+        // Hud line on the terminal row right above the board's frame,
+        // lined up with its left edge
+        fn stamp_hud(&mut self) {
+            let (line, style) = match &self.hud {
+                Some(h) => h.clone(),
+                None => return,
+            };
+            let rows = self.hud_rows();
+            let frame = Vec2i16 { x: self.origin.x - 1, y: self.origin.y - 1 };
+            let pos = match self.board {
+                Some(_) => Vec2i16 { x: frame.x.max(0), y: frame.y / rows - 1 },
+                None => Vec2i16 { x: 0, y: 0 },
+            };
+
+            self.stamp_text(pos, &line, &style);
+        }
+
+        // Text from pos rightwards, glyphs that do not fit whole are left out
+        fn stamp_text(&mut self, pos: Vec2i16, text: &str, style: &Style) {
+            let d = self.terminal_dim;
+            let mut x = pos.x;
+
+            if pos.y < 0 || pos.y >= d.y {
+                return;
+            }
+
+            for c in text.chars() {
+                let w = glyph_width(c) as i16;
+                if x + w > d.x {
+                    break;
+                }
+
+                if x >= 0 {
+                    self.put_cell(Vec2i16 { x, y: pos.y }, Cell { glyph: c, style: *style });
+                }
+                x += w;
+            }
+        }
+
+        fn swap_buffers(&mut self) {
+            swap(&mut self.back_buffer, &mut self.front_buffer);
+        }
+
+        fn steady_render(&mut self) {
+            let w = self.terminal_dim.x as usize;
+            // The bottom right cell is never written,
+            // a terminal scrolls the whole screen when it is
+            let last = self.front_buffer.len().saturating_sub(1);
+
+            for row_start in (0..last).step_by(w.max(1)) {
+                let row_end = (row_start + w).min(last);
+                let mut i = row_start;
+
+                while i < row_end {
+                    if self.back_buffer[i] == self.front_buffer[i] {
+                        i += 1;
+                        continue;
+                    }
+
+                    // A run can not start in the middle of a wide glyph,
+                    // back up to the column the glyph is written at
+                    let mut anchor_point = i;
+                    if self.front_buffer[i].glyph == WIDE_TAIL_CHAR && i > row_start {
+                        anchor_point = i - 1;
+                    }
+
+                    let run_end = self.extend_run(i, row_end);
+
+                    self.move_cursor_to(Vec2i16 {
+                        x: (anchor_point - row_start) as i16,
+                        y: (row_start / w) as i16 });
+                    self.write_cells(anchor_point, run_end);
+
+                    i = run_end;
+                }
+            }
+        }
+
+        // End of the changed run starting at from, short unchanged gaps
+        // are swallowed since rewriting them is cheaper than moving over them
+        fn extend_run(&self, from: usize, row_end: usize) -> usize {
+            let mut end = from + 1;
+
+            loop {
+                while end < row_end && self.back_buffer[end] != self.front_buffer[end] {
+                    end += 1;
+                }
+
+                let mut next = end;
+                while next < row_end && next - end <= REWRITE_GAP &&
+                    self.back_buffer[next] == self.front_buffer[next] &&
+                    self.front_buffer[next].style == self.front_buffer[end - 1].style {
+                    next += 1;
+                }
+
+                if next < row_end && next - end <= REWRITE_GAP &&
+                    self.back_buffer[next] != self.front_buffer[next] {
+                    end = next;
+                    continue;
+                }
+
+                return end;
+            }
+        }
+
+        fn move_cursor_to(&mut self, pos: Vec2i16) {
+            self.backend.move_cursor(self.cursor, pos);
+            self.cursor = Some(pos);
+        }
+
+        // Output front buffer cells in [from, to) at the current cursor
+        // as UTF-8, switching the terminal style only where it actually changes
+        fn write_cells(&mut self, from: usize, to: usize) {
+            let mut glyphs = Vec::<u8>::with_capacity(to.saturating_sub(from));
+            let w = (self.terminal_dim.x as usize).max(1);
+
+            // A wide glyph written last moves the cursor over its tail too,
+            // even when the run stops short of the tail
+            let end = match self.front_buffer.get(to) {
+                Some(cell) if cell.glyph == WIDE_TAIL_CHAR => to + 1,
+                _ => to,
+            };
+
+            // Cursor ends up after the last cell, unless that is past the
+            // right edge where terminals differ in how they hold it
+            self.cursor = match (self.cursor, end % w) {
+                (_, 0) => None,
+                (Some(c), x) if end / w == from / w => Some(Vec2i16 { x: x as i16, y: c.y }),
+                _ => None,
+            };
+
+            for i in from..to {
+                let cell = self.front_buffer[i];
+                if cell.glyph == WIDE_TAIL_CHAR {
+                    continue;
+                }
+
+                if self.pen != Some(cell.style) {
+                    self.backend.write(&glyphs);
+                    glyphs.clear();
+
+                    self.backend.set_style(&cell.style);
+                    self.pen = Some(cell.style);
+                }
+
+                let mut utf8 = [0_u8; 4];
+                glyphs.extend_from_slice(cell.glyph.encode_utf8(&mut utf8).as_bytes());
+            }
+
+            self.backend.write(&glyphs);
+        }
+    }
+}
+
+mod game_logic {
+    use std::collections::{HashMap, VecDeque};
+    use crate::term_input::{Action, ActionQueue};
+    use crate::{term_steady_out::{Handle, MashedPixels, Renderer, Text}, Vec2i16, Square};
+    use crate::{Color, Style, BLOCK_CHAR, DOT_CHAR};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    const WALL_STYLE:  Style = Style { fg: Color::White, bg: Color::Default, bold: false, underline: false };
+    const SNEAK_STYLE: Style = Style { fg: Color::BrightGreen, bg: Color::Default, bold: true, underline: false };
+    const PEACE_STYLE: Style = Style { fg: Color::Green, bg: Color::Default, bold: false, underline: false };
+    const APPLE_STYLE: Style = Style { fg: Color::BrightRed, bg: Color::Default, bold: true, underline: false };
+    const HUD_STYLE:   Style = Style { fg: Color::BrightWhite, bg: Color::Default, bold: false, underline: false };
+    const OVERLAY_STYLE: Style = Style { fg: Color::Black, bg: Color::BrightWhite, bold: true, underline: false };
+
+    // Id the player's sneak has on the occupancy grid
+    const PLAYER: usize = 0;
+
+    // Rules of the game, part of every replay header
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct Rules {
+        pub tick_ms: u64,
+        // Most apples on the board at once
+        pub max_apples: usize,
+        // Ticks between two apple spawns, never zero
+        pub spawn_interval: u64,
+        pub start_direction: Direction,
+    }
+
+    // Everything a game is played with besides its seed
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct GameConfig {
+        pub board: Vec2i16,
+        pub rules: Rules,
+    }
+
+    pub const DEFAULT_RULES: Rules = Rules {
+        tick_ms: 100,
+        max_apples: 12,
+        spawn_interval: 20,
+        start_direction: Direction::Up,
+    };
+
+    pub const DEFAULT_CONFIG: GameConfig = GameConfig {
+        board: Vec2i16 { x: 40, y: 20 },
+        rules: DEFAULT_RULES,
+    };
+
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum State {
+        Running,
+        // Ticks are frozen until P is pressed again
+        Paused,
+        // Sneak died or filled the board, waiting for R or Q
+        GameOver,
+        // A fresh game takes over on the next update
+        Restarting,
+        // Player asked to leave the program
+        Quit,
+    }
+
+    pub struct Game {
+        pub state: State,
+        // Sneak filled the whole board
+        pub won: bool,
+        rules: Rules,
+        // Terminal can not fit the world, nothing moves until it can
+        too_small: bool,
+        world: World,
+        tick: u64,
+        main_actor: Sneak,
+        // Body behind the head, front is the newest segment
+        sneak_peaces: VecDeque<Peace>,
+        // Keyed by the grid cell the apple sits on
+        apples: HashMap<usize, Apple>,
+        input: ActionQueue,
+        seed: u64,
+        rng: ChaCha8Rng,
+        // Every action taken from the input, with the tick it was taken on
+        recording: Vec<(u64, Action)>,
+        // Text over the board while paused or over
+        overlay: Option<Handle>,
+        key_names: [String; 3],
+    }
+
+    struct World {
+        size: Vec2i16,
+        center: Vec2i16,
+        grid: Occupancy,
+    }
+
+    // What is on a cell of the world
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    enum Occupant {
+        Empty,
+        // Anything outside the board
+        Wall,
+        // Head or body segment of the sneak with this id
+        Sneak(usize),
+        Apple(AppleKind),
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    enum AppleKind {
+        Red,
+    }
+
+    // One occupant per board cell, kept up to date as things move.
+    // Free cells are also kept in a set that can be indexed,
+    // so picking one of them at random takes constant time too.
+    struct Occupancy {
+        size: Vec2i16,
+        cells: Vec<Occupant>,
+        free: Vec<usize>,
+        // Where each cell sits in free, usize::MAX when taken
+        free_slot: Vec<usize>,
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Direction {
+        Up,
+        Right,
+        Down,
+        Left,
+    }
+
+    struct Apple {
+        pixels: Handle,
+    }
+
+    struct Peace {
+        pixels: Handle,
+    }
+    
+    struct Sneak {
+        pixels: Handle,
+        direction: Direction,
+        collected: i32,
+    }
+
+    impl World {
+        // Board of the given size, positions inside it run from
+        // (0, 0) to size - 1, the frame around it is the wall
+        pub fn initialize(output: &mut Renderer, size: Vec2i16) -> Self {
+            output.set_board(Some(size), WALL_STYLE);
+
+            World {
+                size,
+                center: Vec2i16 { x: size.x / 2, y: size.y / 2 },
+                grid: Occupancy::initialize(size),
+            }
+        }
+    }
+
+    impl Occupancy {
+        pub fn initialize(size: Vec2i16) -> Self {
+            let n = size.x as usize * size.y as usize;
+
+            Occupancy {
+                size,
+                cells: vec![Occupant::Empty; n],
+                free: (0..n).collect(),
+                free_slot: (0..n).collect(),
+            }
+        }
+
+        pub fn contains(&self, coord: &Vec2i16) -> bool {
+            coord.x >= 0 && coord.y >= 0 && coord.x < self.size.x && coord.y < self.size.y
+        }
+
+        fn index(&self, coord: &Vec2i16) -> Option<usize> {
+            if !self.contains(coord) {
+                return None;
+            }
+            Some(coord.y as usize * self.size.x as usize + coord.x as usize)
+        }
+
+        pub fn at(&self, coord: &Vec2i16) -> Occupant {
+            match self.index(coord) {
+                Some(i) => self.cells[i],
+                None => Occupant::Wall,
+            }
+        }
+
+        // Cells off the board can not be set, they are always wall
+        pub fn set(&mut self, coord: &Vec2i16, occupant: Occupant) {
+            let Some(i) = self.index(coord) else {
+                return;
+            };
+            let was_free = self.cells[i] == Occupant::Empty;
+            let is_free = occupant == Occupant::Empty;
+
+            self.cells[i] = occupant;
+
+            if was_free && !is_free {
+                let slot = self.free_slot[i];
+
+                self.free.swap_remove(slot);
+                if slot < self.free.len() {
+                    self.free_slot[self.free[slot]] = slot;
+                }
+                self.free_slot[i] = usize::MAX;
+            }
+            if !was_free && is_free {
+                self.free_slot[i] = self.free.len();
+                self.free.push(i);
+            }
+        }
+
+        pub fn free_count(&self) -> usize {
+            self.free.len()
+        }
+
+        pub fn nth_free(&self, n: usize) -> Vec2i16 {
+            let i = self.free[n];
+
+            Vec2i16 {
+                x: (i % self.size.x as usize) as i16,
+                y: (i / self.size.x as usize) as i16,
+            }
+        }
+    }
+
+    impl Direction {
+        pub const ALL: [Direction; 4] = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Direction::Up => "up",
+                Direction::Right => "right",
+                Direction::Down => "down",
+                Direction::Left => "left",
+            }
+        }
+
+        pub fn from_name(name: &str) -> Option<Self> {
+            Direction::ALL.into_iter().find(|d| d.name() == name)
+        }
+
+        fn opposite(self) -> Self {
+            match self {
+                Direction::Up => Direction::Down,
+                Direction::Right => Direction::Left,
+                Direction::Down => Direction::Up,
+                Direction::Left => Direction::Right,
+            }
+        }
+    }
+
+    impl Sneak {
+        pub fn initialize(output: &mut Renderer, world: &World, direction: Direction) -> Self {
+            Sneak {
+                pixels: MashedPixels {
+                    sqare: Square { position: (Vec2i16 { 
+                                                    x: world.center.x,
+                                                    y: world.center.y }), 
+                                    size:     (Vec2i16 { x: 1, y: 1 }) },
+                    glyph: BLOCK_CHAR,
+                    style: SNEAK_STYLE,
+                }.initialize(output),
+                direction,
+                collected: 0
+            }
+        }
+    }
+
+    impl Game {
+        // Same seed, board and inputs always play out the same game
+        pub fn initialize(
+            output: &mut Renderer,
+            input: ActionQueue,
+            config: GameConfig,
+            seed: u64) -> Self {
+            let mut w = World::initialize(output, config.board);
+            let ma = Sneak::initialize(output, &w, config.rules.start_direction);
+            w.grid.set(&w.center, Occupant::Sneak(PLAYER));
+
+            Game {
+                state: State::Running,
+                won: false,
+                rules: config.rules,
+                too_small: false,
+                world: w,
+                tick: 0,
+                main_actor: ma,
+                apples: HashMap::<usize, Apple>::new(),
+                sneak_peaces: VecDeque::<Peace>::new(),
+                input,
+                seed,
+                rng: ChaCha8Rng::seed_from_u64(seed),
+                recording: Vec::<(u64, Action)>::new(),
+                overlay: None,
+                key_names: ["P", "Q", "R"].map(String::from),
+            }
+        }
+
+        // How the pause, quit and restart keys are shown to the player
+        pub fn set_key_names(&mut self, names: [String; 3]) {
+            self.key_names = names;
+        }
+        
+        pub fn get_score(&self) -> i32 {
+            self.main_actor.collected
+        }
+
+        pub fn get_length(&self) -> usize {
+            self.sneak_peaces.len() + 1
+        }
+
+        // Time played, paused and held ticks do not count
+        pub fn get_seconds(&self) -> u64 {
+            self.tick * self.rules.tick_ms / 1000
+        }
+
+        pub fn get_seed(&self) -> u64 {
+            self.seed
+        }
+
+        pub fn get_tick(&self) -> u64 {
+            self.tick
+        }
+
+        // Key changes in the order they were read, enough to replay the run
+        pub fn get_recording(&self) -> &Vec<(u64, Action)> {
+            &self.recording
+        }
+
+        // Live stats on the line above the board, mode says what drives the game
+        pub fn update_hud(&self, output: &mut Renderer, mode: &str) {
+            let tick_ms = self.rules.tick_ms.max(1);
+            let seconds = self.get_seconds();
+
+            output.set_hud(Some(format!(
+                "score {}  length {}  time {}:{:02}  speed {}/s  {}",
+                self.get_score(),
+                self.get_length(),
+                seconds / 60, seconds % 60,
+                1000 / tick_ms,
+                mode)), HUD_STYLE);
+        }
+
+        // The renderer keeps the board centered in a resized terminal,
+        // hold the game with a notice while the terminal is too small for it
+        pub fn fit_to_screen(&mut self, output: &mut Renderer) {
+            let d = *output.get_pixel_dim();
+            let e = output.get_board_extent().unwrap_or(d);
+
+            if d.x < e.x || d.y < e.y {
+                let need = output.get_board_cells().unwrap_or(e);
+
+                self.too_small = true;
+                output.set_notice(Some(format!("terminal too small, need {}x{}", need.x, need.y)));
+                self.update_overlay(output);
+                return;
+            }
+
+            self.too_small = false;
+            output.set_notice(None);
+            self.update_overlay(output);
+        }
+
+        // A game held by a terminal too small for it does not step,
+        // it can still be paused or quit
+        pub fn update(&mut self, output: &mut Renderer) {
+            match self.state {
+                State::Running if self.too_small => self.take_held_action(),
+                State::Running => self.step(output),
+                State::Paused | State::GameOver => self.take_control_action(output),
+                State::Restarting => self.restart(output),
+                State::Quit => {}
+            }
+        }
+
+        // One tick of play
+        fn step(&mut self, output: &mut Renderer) {
+            let boost = self.take_turn();
+            if self.state != State::Running {
+                self.update_overlay(output);
+                return;
+            }
+
+            // A boost moves the sneak a second cell within the same tick
+            for _ in 0..if boost { 2 } else { 1 } {
+                if self.state == State::Running {
+                    self.advance(output);
+                }
+            }
+
+            if self.state == State::Running &&
+                self.tick.is_multiple_of(self.rules.spawn_interval) &&
+                self.apples.len() < self.rules.max_apples {
+                self.spawn_apple(output);
+            }
+            if self.state == State::GameOver {
+                self.update_overlay(output);
+            }
+
+            self.tick += 1;
+            if self.tick == u64::MAX {
+                self.tick = 0;
+            }
+        }
+
+        // Move the head one cell on and let whatever it runs into happen
+        fn advance(&mut self, output: &mut Renderer) {
+            let pos = Game::get_pos(output, &self.main_actor.pixels);
+            let last_pos: Vec2i16 = Vec2i16 {
+                x: pos.x,
+                y: pos.y,
+            };
+
+            match self.main_actor.direction {
+                Direction::Up => {
+                    Game::set_pos(output, &self.main_actor.pixels, Vec2i16 {
+                        x: pos.x,
+                        y: pos.y - 1 });
+                }
+                Direction::Right => {
+                    Game::set_pos(output, &self.main_actor.pixels, Vec2i16 { 
+                        x: pos.x + 1,
+                        y: pos.y });
+                }
+                Direction::Down => {
+                    Game::set_pos(output, &self.main_actor.pixels, Vec2i16 { 
+                        x: pos.x, 
+                        y: pos.y + 1 });
+                }
+                Direction::Left => {
+                    Game::set_pos(output, &self.main_actor.pixels, Vec2i16 { 
+                        x: pos.x - 1, 
+                        y: pos.y });
+                }
+            }
+        
+            let cur_snake_pos = Game::get_pos(output, &self.main_actor.pixels);
+            if crate::game_logic::Game::check_is_in_deadly_collison(self, &cur_snake_pos) {
+                self.state = State::GameOver;
+            }
+            else {
+                self.move_body(output, last_pos, cur_snake_pos);
+            }
+
+            let area = self.world.size.x as usize * self.world.size.y as usize;
+            if self.state == State::Running && self.sneak_peaces.len() + 1 >= area {
+                self.won = true;
+                self.state = State::GameOver;
+            }
+        }
+
+        // The body follows by taking the spot the head left,
+        // growing keeps the tail where it is
+        fn move_body(&mut self, output: &mut Renderer, last_pos: Vec2i16, cur_snake_pos: Vec2i16) {
+            if crate::game_logic::Game::check_is_in_happy_collison(self, output, &cur_snake_pos) {
+                self.main_actor.collected += 1;
+                self.sneak_peaces.push_front(Peace {
+                    pixels: MashedPixels {
+                        sqare: Square { position: last_pos,
+                                        size:     (Vec2i16 { x: 1, y: 1 }) },
+                        glyph: BLOCK_CHAR,
+                        style: PEACE_STYLE,
+                    }.initialize(output),
+                });
+            }
+            else if let Some(tail) = self.sneak_peaces.pop_back() {
+                self.world.grid.set(&Game::get_pos(output, &tail.pixels), Occupant::Empty);
+                Game::set_pos(output, &tail.pixels, last_pos);
+                self.sneak_peaces.push_front(tail);
+            }
+            else {
+                self.world.grid.set(&last_pos, Occupant::Empty);
+            }
+
+            self.world.grid.set(&cur_snake_pos, Occupant::Sneak(PLAYER));
+        }
+
+        // Take queued actions until one of them turns the sneak, so every
+        // press gets its own tick. Moves that keep the direction or would
+        // reverse it straight into the body are dropped. Returns whether
+        // a boost was taken on the way.
+        fn take_turn(&mut self) -> bool {
+            let mut boost = false;
+
+            while let Some(action) = self.pop_action() {
+                let turn = match action {
+                    Action::MoveUp => Direction::Up,
+                    Action::MoveRight => Direction::Right,
+                    Action::MoveDown => Direction::Down,
+                    Action::MoveLeft => Direction::Left,
+                    Action::Boost => {
+                        boost = true;
+                        continue;
+                    }
+                    Action::Pause => {
+                        self.state = State::Paused;
+                        break;
+                    }
+                    Action::Quit => {
+                        self.state = State::Quit;
+                        break;
+                    }
+                    Action::Restart | Action::Confirm => continue,
+                };
+                let current = self.main_actor.direction;
+
+                if turn == current {
+                    continue;
+                }
+                if turn == current.opposite() && !self.sneak_peaces.is_empty() {
+                    continue;
+                }
+
+                self.main_actor.direction = turn;
+                break;
+            }
+            boost
+        }
+
+        // While paused or over only pause, restart and quit mean anything,
+        // the first one that applies to the state is acted upon
+        fn take_control_action(&mut self, output: &mut Renderer) {
+            while let Some(action) = self.pop_action() {
+                match (self.state, action) {
+                    (_, Action::Quit) => self.state = State::Quit,
+                    (State::Paused, Action::Pause) => self.state = State::Running,
+                    (State::GameOver, Action::Restart) => self.state = State::Restarting,
+                    _ => continue,
+                }
+
+                self.update_overlay(output);
+                return;
+            }
+        }
+
+        // Pause and quit are taken out of turn while held, the moves
+        // queued before them wait for the game to go on
+        fn take_held_action(&mut self) {
+            let mut input = self.input.lock().unwrap();
+            let k = match input.iter().position(|a| matches!(a, Action::Pause | Action::Quit)) {
+                Some(k) => k,
+                None => return,
+            };
+            let action = input.remove(k).unwrap();
+            drop(input);
+
+            self.recording.push((self.tick, action));
+            self.state = if action == Action::Quit { State::Quit } else { State::Paused };
+        }
+
+        // Everything taken from the input goes into the recording
+        fn pop_action(&mut self) -> Option<Action> {
+            let action = self.input.lock().unwrap().pop_front()?;
+
+            self.recording.push((self.tick, action));
+            Some(action)
+        }
+
+        // Same board, rules and input, the seed is drawn from the
+        // current one so a chain of restarts is still reproducible
+        fn restart(&mut self, output: &mut Renderer) {
+            use rand::Rng;
+
+            self.destroy(output);
+
+            let seed = self.rng.gen();
+            let config = GameConfig { board: self.world.size, rules: self.rules };
+            let key_names = std::mem::take(&mut self.key_names);
+            let too_small = self.too_small;
+
+            *self = Game::initialize(output, self.input.clone(), config, seed);
+            self.key_names = key_names;
+            self.too_small = too_small;
+        }
+
+        // Take everything the game put into the renderer back out,
+        // leaving it as it was before the game was initialized
+        pub fn destroy(&mut self, output: &mut Renderer) {
+            let handles = self.apples.values().map(|a| a.pixels)
+                .chain(self.sneak_peaces.iter().map(|p| p.pixels))
+                .chain(std::iter::once(self.main_actor.pixels));
+            for h in handles {
+                output.objects_mut().remove(&h);
+            }
+            if let Some(h) = self.overlay.take() {
+                output.texts_mut().remove(&h);
+            }
+
+            self.apples.clear();
+            self.sneak_peaces.clear();
+            output.set_board(None, Style::default());
+            output.set_hud(None, Style::default());
+            output.set_notice(None);
+        }
+
+        // Show what the state waits for centered on the screen, or nothing
+        fn update_overlay(&mut self, output: &mut Renderer) {
+            if let Some(h) = self.overlay.take() {
+                output.texts_mut().remove(&h);
+            }
+
+            let [pause, quit, restart] = &self.key_names;
+            let content = match self.state {
+                // The notice holding the game takes the overlay's place
+                _ if self.too_small => return,
+                State::Paused => format!(" paused, {} to resume, {} to quit ", pause, quit),
+                State::GameOver if self.won => format!(" board cleared, seed {}, {} to restart, {} to quit ",
+                                                       self.get_seed(), restart, quit),
+                State::GameOver => format!(" game over, seed {}, {} to restart, {} to quit ",
+                                           self.get_seed(), restart, quit),
+                _ => return,
+            };
+            let d = *output.get_terminal_dim();
+            let width = content.chars().count() as i16;
+
+            self.overlay = Some(Text {
+                position: Vec2i16 { x: ((d.x - width) / 2).max(0), y: d.y / 2 },
+                content: content.to_string(),
+                style: OVERLAY_STYLE,
+            }.initialize(output));
+        }
+
+        // Put an apple on a cell picked uniformly among the free ones,
+        // nothing spawns while the board has no room left
+        fn spawn_apple(&mut self, output: &mut Renderer) {
+            let free = self.world.grid.free_count();
+            if free == 0 {
+                return;
+            }
+
+            let n = self.random_index(free);
+            let pos = self.world.grid.nth_free(n);
+            let cell = self.world.grid.index(&pos).unwrap();
+
+            self.world.grid.set(&pos, Occupant::Apple(AppleKind::Red));
+            self.apples.insert(cell, Apple {
+                pixels: MashedPixels {
+                    sqare: Square { position: pos,
+                                    size:     (Vec2i16 { x: 1, y: 1 }) },
+                    glyph: DOT_CHAR,
+                    style: APPLE_STYLE,
+                }.initialize(output),
+            });
+        }
+
+        // Every random decision goes through here,
+        // so a run is fully determined by its seed
+        fn random_index(&mut self, len: usize) -> usize {
+            use rand::Rng;
+            self.rng.gen_range(0..len)
+        }
+
+        fn get_pos(output: &Renderer, handle: &Handle) -> Vec2i16 {
+            *output.objects().get(handle).unwrap().get_pos()
+        }
+
+        fn set_pos(output: &mut Renderer, handle: &Handle, pos: Vec2i16) {
+            output.objects_mut().get_mut(handle).unwrap().set_pos(pos);
+        }
+
+        fn check_is_in_deadly_collison(&self, coord: &Vec2i16) -> bool {
+            // Leaving the board means running into the wall
+            matches!(self.world.grid.at(coord), Occupant::Wall | Occupant::Sneak(_))
+        }
+
+        fn check_is_in_happy_collison(&mut self, output: &mut Renderer, coord: &Vec2i16) -> bool {
+            if !matches!(self.world.grid.at(coord), Occupant::Apple(_)) {
+                return false;
+            }
+
+            let cell = self.world.grid.index(coord).unwrap();
+            if let Some(apple) = self.apples.remove(&cell) {
+                output.objects_mut().remove(&apple.pixels);
+            }
+            self.world.grid.set(coord, Occupant::Empty);
+            true
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::term_backend::VirtualBackend;
+
+        // Game on a board in a terminal large enough for it, with no apples
+        // spawning unless the rules given say so
+        fn game(board: Vec2i16, rules: Rules) -> (Renderer, ActionQueue, Game) {
+            let mut r = Renderer::initialize(Box::new(VirtualBackend::initialize(Vec2i16 { x: 40, y: 20 })));
+            let input: ActionQueue = Default::default();
+            let g = Game::initialize(&mut r, input.clone(), GameConfig { board, rules }, 7);
+
+            (r, input, g)
+        }
+
+        fn no_apples() -> Rules {
+            Rules { max_apples: 0, ..DEFAULT_RULES }
+        }
+
+        fn head(r: &Renderer, g: &Game) -> Vec2i16 {
+            Game::get_pos(r, &g.main_actor.pixels)
+        }
+
+        #[test]
+        fn two_presses_in_one_tick_each_get_a_tick() {
+            let (mut r, input, mut g) = game(Vec2i16 { x: 20, y: 10 }, no_apples());
+            input.lock().unwrap().extend([Action::MoveLeft, Action::MoveDown]);
+
+            g.update(&mut r);
+            assert_eq!(g.main_actor.direction, Direction::Left);
+            assert_eq!(head(&r, &g), Vec2i16 { x: 9, y: 5 });
+
+            g.update(&mut r);
+            assert_eq!(g.main_actor.direction, Direction::Down);
+            assert_eq!(head(&r, &g), Vec2i16 { x: 9, y: 6 });
+        }
+
+        #[test]
+        fn presses_that_do_not_turn_do_not_take_a_tick() {
+            let (mut r, input, mut g) = game(Vec2i16 { x: 20, y: 10 }, no_apples());
+            input.lock().unwrap().extend([Action::MoveUp, Action::Boost, Action::MoveUp, Action::MoveRight]);
+
+            g.update(&mut r);
+            assert_eq!(g.main_actor.direction, Direction::Right);
+            assert_eq!(head(&r, &g), Vec2i16 { x: 12, y: 5 });
+            assert!(input.lock().unwrap().is_empty());
+        }
+
+        #[test]
+        fn reversing_into_the_body_is_dropped() {
+            let (mut r, input, mut g) = game(Vec2i16 { x: 20, y: 10 }, no_apples());
+            g.world.grid.set(&Vec2i16 { x: 10, y: 4 }, Occupant::Apple(AppleKind::Red));
+
+            g.update(&mut r);
+            assert_eq!(g.get_length(), 2);
+
+            input.lock().unwrap().extend([Action::MoveDown, Action::MoveLeft]);
+            g.update(&mut r);
+            assert_eq!(g.main_actor.direction, Direction::Left);
+            assert_eq!(head(&r, &g), Vec2i16 { x: 9, y: 4 });
+            assert!(g.state == State::Running);
+        }
+
+        #[test]
+        fn a_lone_head_can_reverse() {
+            let (mut r, input, mut g) = game(Vec2i16 { x: 20, y: 10 }, no_apples());
+            input.lock().unwrap().push_back(Action::MoveDown);
+
+            g.update(&mut r);
+            assert_eq!(g.main_actor.direction, Direction::Down);
+            assert_eq!(head(&r, &g), Vec2i16 { x: 10, y: 6 });
+            assert!(g.state == State::Running);
+        }
+
+        // Free cells as the set has them, each one actually empty and listed once
+        fn check_free(grid: &Occupancy) {
+            let mut free: Vec<usize> = (0..grid.free_count())
+                .map(|n| grid.nth_free(n))
+                .inspect(|c| assert_eq!(grid.at(c), Occupant::Empty))
+                .map(|c| grid.index(&c).unwrap())
+                .collect();
+            free.sort();
+            free.dedup();
+
+            assert_eq!(free.len(), grid.free_count());
+            assert_eq!(free.len(), grid.cells.iter().filter(|c| **c == Occupant::Empty).count());
+        }
+
+        #[test]
+        fn free_cells_stay_apart_from_taken_ones() {
+            let size = Vec2i16 { x: 4, y: 3 };
+            let mut grid = Occupancy::initialize(size);
+            let cell = |i: usize| Vec2i16 { x: (i % 4) as i16, y: (i / 4) as i16 };
+
+            // Taken and freed in an order unlike the one they are kept in
+            for i in (0..12).map(|i| i * 5 % 12) {
+                grid.set(&cell(i), Occupant::Sneak(0));
+                check_free(&grid);
+            }
+            assert_eq!(grid.free_count(), 0);
+
+            for i in [7, 0, 11, 3, 6] {
+                grid.set(&cell(i), Occupant::Empty);
+                check_free(&grid);
+            }
+            for i in [0, 6, 2, 7] {
+                grid.set(&cell(i), Occupant::Apple(AppleKind::Red));
+                check_free(&grid);
+            }
+            // Replacing one taker with another, or freeing a free cell, changes nothing
+            grid.set(&cell(1), Occupant::Apple(AppleKind::Red));
+            grid.set(&cell(3), Occupant::Empty);
+            grid.set(&Vec2i16 { x: -1, y: 0 }, Occupant::Empty);
+            check_free(&grid);
+            assert_eq!(grid.free_count(), 2);
+        }
+
+        #[test]
+        fn filling_a_two_by_two_board_wins() {
+            let rules = Rules { max_apples: 4, spawn_interval: 1, ..DEFAULT_RULES };
+            let (mut r, input, mut g) = game(Vec2i16 { x: 2, y: 2 }, rules);
+
+            // Round the board so the sneak never runs into itself
+            for _ in 0..100 {
+                let turn = match head(&r, &g) {
+                    Vec2i16 { x: 1, y: 0 } => Action::MoveLeft,
+                    Vec2i16 { x: 0, y: 0 } => Action::MoveDown,
+                    Vec2i16 { x: 0, y: 1 } => Action::MoveRight,
+                    _ => Action::MoveUp,
+                };
+                input.lock().unwrap().push_back(turn);
+                g.update(&mut r);
+
+                for cell in g.apples.keys() {
+                    assert_eq!(g.world.grid.cells[*cell], Occupant::Apple(AppleKind::Red));
+                }
+                check_free(&g.world.grid);
+                if g.state != State::Running {
+                    break;
+                }
+            }
+
+            assert!(g.state == State::GameOver && g.won);
+            assert_eq!(g.get_length(), 4);
+            assert_eq!(g.world.grid.free_count(), 0);
+        }
+    }
+}
+
+mod replay {
+    use std::fs;
+    use std::path::Path;
+    use crate::term_input::{Action, ActionQueue};
+    use crate::game_logic::{Direction, GameConfig, Rules};
+    use crate::settings;
+
+    const MAGIC: &str = "sneak-replay";
+    // Bumped whenever the same inputs could play out differently
+    const VERSION: u32 = 5;
+
+    // Everything besides the inputs that decides how a run plays out
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct Header {
+        pub seed: u64,
+        pub half_block: bool,
+        pub config: GameConfig,
+    }
+
+    pub struct Replay {
+        pub header: Header,
+        events: Vec<(u64, Action)>,
+        next: usize,
+        pub end_tick: u64,
+        pub score: i32,
+    }
+
+    // Plain text, one line of header fields each,
+    // then one "tick action" line per action the game took
+    pub fn save(
+        path: &Path,
+        header: &Header,
+        events: &[(u64, Action)],
+        end_tick: u64,
+        score: i32) -> std::io::Result<()> {
+        let mut out = format!(
+            "{} {}\nseed {}\nboard {}x{}\nmode {}\nrules {} {} {} {}\n",
+            MAGIC, VERSION,
+            header.seed,
+            header.config.board.x, header.config.board.y,
+            settings::mode_name(header.half_block),
+            header.config.rules.tick_ms,
+            header.config.rules.max_apples,
+            header.config.rules.spawn_interval,
+            header.config.rules.start_direction.name());
+
+        for (tick, action) in events {
+            out.push_str(&format!("{} {}\n", tick, action.name()));
+        }
+        out.push_str(&format!("end {} {}\n", end_tick, score));
+
+        fs::write(path, out)
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let shown = path.display();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("can not read replay {}: {}", shown, e))?;
+        let mut lines = text.lines().enumerate();
+        let mut field = |name: &str| -> Result<Vec<String>, String> {
+            let (n, line) = lines.next().ok_or(format!("{}: missing {}", shown, name))?;
+            let mut words = line.split_whitespace();
+
+            if words.next() != Some(name) {
+                return Err(format!("{}:{}: expected {}", shown, n + 1, name));
+            }
+            Ok(words.map(String::from).collect())
+        };
+        let bad = |name: &str| format!("{}: malformed {}", shown, name);
+
+        let version: u32 = field(MAGIC)?
+            .first().and_then(|v| v.parse().ok()).ok_or(bad("version"))?;
+        if version != VERSION {
+            return Err(format!("{}: replay version {} is not supported", shown, version));
+        }
+
+        let seed: u64 = field("seed")?
+            .first().and_then(|v| v.parse().ok()).ok_or(bad("seed"))?;
+        let board = field("board")?
+            .first()
+            .ok_or(bad("board"))
+            .and_then(|v| settings::parse_board(v).map_err(|e| format!("{}: board: {}", shown, e)))?;
+        let half_block = field("mode")?
+            .first().and_then(|v| settings::parse_mode(v).ok()).ok_or(bad("mode"))?;
+        let rules = field("rules")?;
+        let number = |v: &str, range| -> Result<u64, String> {
+            let n = v.parse().map_err(|_| bad("rules"))?;
+            settings::check_range(n, range).map_err(|e| format!("{}: rules: {}", shown, e))
+        };
+        let rules = match rules.as_slice() {
+            [tick_ms, max_apples, spawn_interval, direction] => Rules {
+                tick_ms: number(tick_ms, settings::TICK_MS_RANGE)?,
+                max_apples: number(max_apples, settings::MAX_APPLES_RANGE)? as usize,
+                spawn_interval: number(spawn_interval, settings::SPAWN_INTERVAL_RANGE)?,
+                start_direction: Direction::from_name(direction).ok_or(bad("rules"))?,
+            },
+            _ => return Err(bad("rules")),
+        };
+
+        let header = Header {
+            seed,
+            half_block,
+            config: GameConfig { board, rules },
+        };
+
+        let mut events = Vec::<(u64, Action)>::new();
+        let mut end = None;
+        for (n, line) in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let malformed = || format!("{}:{}: malformed input", shown, n + 1);
+
+            match words.as_slice() {
+                ["end", tick, score] => {
+                    end = Some((
+                        tick.parse().map_err(|_| malformed())?,
+                        score.parse().map_err(|_| malformed())?));
+                    break;
+                }
+                [tick, action] => events.push((
+                    tick.parse().map_err(|_| malformed())?,
+                    Action::from_name(action).ok_or_else(malformed)?)),
+                [] => continue,
+                _ => return Err(malformed()),
+            }
+        }
+
+        let (end_tick, score) = end.ok_or(format!("{}: missing end", shown))?;
+
+        Ok(Replay { header, events, next: 0, end_tick, score })
+    }
+
+    impl Replay {
+        // Queue the actions recorded for this tick where the game reads input from
+        pub fn feed(&mut self, tick: u64, input: &ActionQueue) {
+            let mut q = input.lock().unwrap();
+
+            while self.next < self.events.len() && self.events[self.next].0 <= tick {
+                q.push_back(self.events[self.next].1);
+                self.next += 1;
+            }
+        }
+    }
+}
+
+mod settings {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::{Vec2i16, A_KEY, BACKSPACE_KEY, D_KEY, ENTER_KEY, P_KEY, Q_KEY, R_KEY, S_KEY, W_KEY};
+    use crate::{DOWN_KEY, H_KEY, J_KEY, K_KEY, LEFT_KEY, L_KEY, NUMPAD_0_KEY, RIGHT_KEY, SPACE_KEY, UP_KEY};
+    use crate::game_logic::{Direction, GameConfig, DEFAULT_CONFIG};
+    use crate::term_input::Action;
+
+    // Key each of Action::BINDABLE starts out bound to
+    const DEFAULT_BINDINGS: [u8; 8] = [W_KEY, A_KEY, S_KEY, D_KEY, P_KEY, Q_KEY, R_KEY, SPACE_KEY];
+
+    // Keys that work besides the bound ones, arrows, vi keys and the
+    // numpad. A key bound to some action means that action instead.
+    const ALTERNATES: [(u8, Action); 13] = [
+        (UP_KEY, Action::MoveUp),
+        (LEFT_KEY, Action::MoveLeft),
+        (DOWN_KEY, Action::MoveDown),
+        (RIGHT_KEY, Action::MoveRight),
+        (K_KEY, Action::MoveUp),
+        (H_KEY, Action::MoveLeft),
+        (J_KEY, Action::MoveDown),
+        (L_KEY, Action::MoveRight),
+        (NUMPAD_0_KEY + 8, Action::MoveUp),
+        (NUMPAD_0_KEY + 4, Action::MoveLeft),
+        (NUMPAD_0_KEY + 2, Action::MoveDown),
+        (NUMPAD_0_KEY + 6, Action::MoveRight),
+        (ENTER_KEY, Action::Confirm),
+    ];
+
+    // Keys with a name of their own, the rest are letters and digits
+    const KEY_NAMES: [(u8, &str); 7] = [
+        (ENTER_KEY, "enter"),
+        (BACKSPACE_KEY, "backspace"),
+        (SPACE_KEY, "space"),
+        (UP_KEY, "up"),
+        (LEFT_KEY, "left"),
+        (DOWN_KEY, "down"),
+        (RIGHT_KEY, "right"),
+    ];
+
+    // Choices the settings screen steps through
+    pub const TICK_CHOICES:  [u64; 5] = [200, 150, 100, 75, 50];
+    pub const BOARD_CHOICES: [Vec2i16; 5] = [
+        Vec2i16 { x: 20, y: 10 },
+        Vec2i16 { x: 30, y: 15 },
+        Vec2i16 { x: 40, y: 20 },
+        Vec2i16 { x: 60, y: 24 },
+        Vec2i16 { x: 80, y: 30 },
+    ];
+
+    // Bounds the config file and command line are checked against
+    pub const TICK_MS_RANGE: (u64, u64) = (10, 5000);
+    pub const BOARD_SIDE_RANGE: (u64, u64) = (2, 1000);
+    pub const MAX_APPLES_RANGE: (u64, u64) = (0, 10000);
+    pub const SPAWN_INTERVAL_RANGE: (u64, u64) = (1, 10000);
+
+    // What the game is played with, from the config file and the menus
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct Settings {
+        pub config: GameConfig,
+        pub half_block: bool,
+        // Key bound to each of Action::BINDABLE
+        pub bindings: [u32; 8],
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                config: DEFAULT_CONFIG,
+                half_block: false,
+                bindings: DEFAULT_BINDINGS.map(|k| k as u32),
+            }
+        }
+    }
+
+    impl Settings {
+        // What a pressed key means to the game and menus, if anything
+        pub fn translate(&self, key: u32) -> Option<Action> {
+            if let Some(k) = self.bindings.iter().position(|b| *b == key) {
+                return Some(Action::BINDABLE[k]);
+            }
+            ALTERNATES.iter().find(|(k, _)| *k as u32 == key).map(|(_, a)| *a)
+        }
+
+        // Bind the action to the key, an action that had the key
+        // before gets this one's old key so no key does two things
+        pub fn bind(&mut self, action: Action, key: u32) -> bool {
+            let slot = match slot(action) {
+                Some(s) if can_bind(key) => s,
+                _ => return false,
+            };
+            if let Some(other) = self.bindings.iter().position(|b| *b == key) {
+                self.bindings[other] = self.bindings[slot];
+            }
+            self.bindings[slot] = key;
+            true
+        }
+
+        // Name of the key the action is bound to, as the player is shown it
+        pub fn key_name(&self, action: Action) -> String {
+            match slot(action) {
+                Some(s) => key_name(self.bindings[s]),
+                None => key_name(ENTER_KEY as u32),
+            }
+        }
+    }
+
+    fn slot(action: Action) -> Option<usize> {
+        Action::BINDABLE.iter().position(|a| *a == action)
+    }
+
+    // Enter and backspace keep their meaning in menus and name entry
+    fn can_bind(key: u32) -> bool {
+        ![ENTER_KEY, BACKSPACE_KEY].iter().any(|k| *k as u32 == key)
+    }
+
+    pub fn key_name(key: u32) -> String {
+        let k = match u8::try_from(key) {
+            Ok(k) => k,
+            Err(_) => return format!("#{}", key),
+        };
+
+        if let Some((_, name)) = KEY_NAMES.iter().find(|(n, _)| *n == k) {
+            return name.to_string();
+        }
+        match k {
+            _ if k.is_ascii_uppercase() || k.is_ascii_digit() => (k as char).to_string(),
+            _ if (NUMPAD_0_KEY..NUMPAD_0_KEY + 10).contains(&k) => format!("num{}", k - NUMPAD_0_KEY),
+            _ => format!("#{}", key),
+        }
+    }
+
+    // Key from the name key_name gives it, letters in either case
+    pub fn key_from_name(name: &str) -> Option<u32> {
+        let lower = name.to_ascii_lowercase();
+
+        if let Some((k, _)) = KEY_NAMES.iter().find(|(_, n)| *n == lower) {
+            return Some(*k as u32);
+        }
+        if let Some(d) = lower.strip_prefix("num").and_then(|d| d.parse::<u8>().ok()).filter(|d| *d < 10) {
+            return Some((NUMPAD_0_KEY + d) as u32);
+        }
+        match name.as_bytes() {
+            [k] if k.is_ascii_alphanumeric() => Some(k.to_ascii_uppercase() as u32),
+            _ => None,
+        }
+    }
+
+    // Kinds of per-user files the program keeps
+    #[derive(Copy, Clone)]
+    pub enum UserDir {
+        Config,
+        Data,
+    }
+
+    // Directory of the program's own files of the kind, under
+    // $XDG_CONFIG_HOME or $XDG_DATA_HOME, falling back to ~/.config
+    // and ~/.local/share when unset or not absolute
+    #[cfg(unix)]
+    pub fn user_dir(kind: UserDir) -> Option<PathBuf> {
+        let (var, under_home) = match kind {
+            UserDir::Config => ("XDG_CONFIG_HOME", ".config"),
+            UserDir::Data => ("XDG_DATA_HOME", ".local/share"),
+        };
+        let base = std::env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(under_home)))?;
+
+        Some(base.join("sneak"))
+    }
+
+    #[cfg(windows)]
+    pub fn user_dir(kind: UserDir) -> Option<PathBuf> {
+        let var = match kind {
+            UserDir::Config => "APPDATA",
+            UserDir::Data => "LOCALAPPDATA",
+        };
+
+        std::env::var_os(var).map(|b| PathBuf::from(b).join("sneak"))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        user_dir(UserDir::Config).map(|d| d.join("config.toml"))
+    }
+
+    // Value on the right of "name = value", strings lose their quotes
+    enum Value {
+        Int(u64),
+        Str(String),
+    }
+
+    fn parse_value(v: &str) -> Option<Value> {
+        if let Some(inner) = v.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            return Some(Value::Str(inner.to_string()));
+        }
+        v.replace('_', "").parse().ok().map(Value::Int)
+    }
+
+    pub fn check_range(n: u64, (low, high): (u64, u64)) -> Result<u64, String> {
+        if !(low..=high).contains(&n) {
+            return Err(format!("{} is out of range {}..={}", n, low, high));
+        }
+        Ok(n)
+    }
+
+    fn int_in(v: &Value, range: (u64, u64)) -> Result<u64, String> {
+        match v {
+            Value::Int(n) => check_range(*n, range),
+            Value::Str(_) => Err("expected a number".to_string()),
+        }
+    }
+
+    fn str_of(v: &Value) -> Result<&str, String> {
+        match v {
+            Value::Str(s) => Ok(s),
+            Value::Int(_) => Err("expected a quoted string".to_string()),
+        }
+    }
+
+    // A letter or digit, a key name like "space", "up" or "num8", or a key code
+    fn key_of(v: &Value) -> Result<u32, String> {
+        let key = match v {
+            Value::Int(n) => u32::try_from(*n).map_err(|_| format!("key code {} is too large", n))?,
+            Value::Str(s) => key_from_name(s)
+                .ok_or(format!("\"{}\" is not a key name, use a key code", s))?,
+        };
+        if !can_bind(key) {
+            return Err(format!("{} can not be bound", key_name(key)));
+        }
+        Ok(key)
+    }
+
+    fn board_of(v: &Value) -> Result<Vec2i16, String> {
+        parse_board(str_of(v)?)
+    }
+
+    // How the pixel mode is written wherever it is stored or given,
+    // half_block tells the two apart
+    pub fn mode_name(half_block: bool) -> &'static str {
+        if half_block { "half-block" } else { "cell" }
+    }
+
+    pub fn parse_mode(v: &str) -> Result<bool, String> {
+        match v {
+            "cell" => Ok(false),
+            "half-block" => Ok(true),
+            _ => Err(format!("\"{}\" is not one of cell, half-block", v)),
+        }
+    }
+
+    // "WIDTHxHEIGHT" with both sides in range
+    pub fn parse_board(v: &str) -> Result<Vec2i16, String> {
+        let size = v
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u64>().ok()?, h.parse::<u64>().ok()?)))
+            .ok_or("expected \"WIDTHxHEIGHT\"")?;
+        let (low, high) = BOARD_SIDE_RANGE;
+
+        if !(low..=high).contains(&size.0) || !(low..=high).contains(&size.1) {
+            return Err(format!("sides must be in range {}..={}", low, high));
+        }
+        Ok(Vec2i16 { x: size.0 as i16, y: size.1 as i16 })
+    }
+
+    // Line up to a "#" that is not inside a string
+    fn strip_comment(line: &str) -> &str {
+        let mut quoted = false;
+
+        for (k, c) in line.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                '#' if !quoted => return &line[..k],
+                _ => {}
+            }
+        }
+        line
+    }
+
+    // Settings from a config file in a small subset of toml, top level
+    // "name = value" lines and a [keys] table, "#" starts a comment
+    pub fn parse(text: &str) -> Result<Settings, (usize, String)> {
+        let mut s = Settings::default();
+        let mut section = "";
+        let mut seen: Vec<String> = Vec::new();
+        // Line each action's key was set on, 0 for defaults
+        let mut key_lines = [0usize; 8];
+
+        for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l)) {
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim() {
+                    "keys" => "keys",
+                    other => return Err((n, format!("unknown table [{}]", other))),
+                };
+                continue;
+            }
+
+            let (name, value) = line.split_once('=').ok_or((n, "expected name = value".to_string()))?;
+            let name = name.trim();
+            let value = parse_value(value.trim()).ok_or((n, format!("malformed value for {}", name)))?;
+            let full = if section.is_empty() { name.to_string() } else { format!("{}.{}", section, name) };
+
+            if seen.contains(&full) {
+                return Err((n, format!("{} is set twice", full)));
+            }
+            seen.push(full.clone());
+
+            let mut set = |s: &mut Settings| -> Result<(), String> {
+                let rules = &mut s.config.rules;
+
+                match (section, name) {
+                    ("", "tick_ms") => rules.tick_ms = int_in(&value, TICK_MS_RANGE)?,
+                    ("", "board") => s.config.board = board_of(&value)?,
+                    ("", "max_apples") => rules.max_apples = int_in(&value, MAX_APPLES_RANGE)? as usize,
+                    ("", "spawn_interval") => rules.spawn_interval = int_in(&value, SPAWN_INTERVAL_RANGE)?,
+                    ("", "start_direction") => {
+                        let d = str_of(&value)?;
+                        rules.start_direction = Direction::from_name(d)
+                            .ok_or(format!("\"{}\" is not one of up, left, down, right", d))?;
+                    }
+                    ("", "mode") => s.half_block = parse_mode(str_of(&value)?)?,
+                    ("keys", _) => {
+                        let slot = Action::BINDABLE.iter().position(|a| a.name() == name)
+                            .ok_or("unknown action")?;
+                        s.bindings[slot] = key_of(&value)?;
+                        key_lines[slot] = n;
+                    }
+                    _ => return Err("unknown setting".to_string()),
+                }
+                Ok(())
+            };
+            set(&mut s).map_err(|e| (n, format!("{}: {}", full, e)))?;
+        }
+
+        // Keys that were not set keep their default unless that was taken
+        for (k, key) in s.bindings.iter().enumerate() {
+            if let Some(other) = s.bindings[..k].iter().position(|b| b == key) {
+                return Err((key_lines[k].max(key_lines[other]), format!(
+                    "keys: {} and {} are both bound to {}",
+                    Action::BINDABLE[other].name(), Action::BINDABLE[k].name(), key_name(*key))));
+            }
+        }
+
+        Ok(s)
+    }
+
+    // Settings from the config file, defaults when there is none
+    // unless the file was asked for by name
+    pub fn load(path: &Path, must_exist: bool) -> Result<Settings, String> {
+        if !must_exist && !path.exists() {
+            return Ok(Settings::default());
+        }
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("can not read config {}: {}", path.display(), e))?;
+
+        parse(&text).map_err(|(n, e)| format!("{}:{}: {}", path.display(), n, e))
+    }
+
+    fn key_value(key: u32) -> String {
+        let name = key_name(key);
+
+        match key_from_name(&name) {
+            Some(_) => format!("\"{}\"", name),
+            None => key.to_string(),
+        }
+    }
+
+    pub fn save(s: &Settings, path: &Path) -> Result<(), String> {
+        let rules = &s.config.rules;
+        let mut text = format!(
+            "# sneak config, rewritten when settings change in the menus\n\
+             tick_ms = {}\nboard = \"{}x{}\"\nmax_apples = {}\nspawn_interval = {}\n\
+             start_direction = \"{}\"\nmode = \"{}\"\n\n[keys]\n",
+            rules.tick_ms,
+            s.config.board.x, s.config.board.y,
+            rules.max_apples,
+            rules.spawn_interval,
+            rules.start_direction.name(),
+            mode_name(s.half_block));
+
+        for (action, key) in Action::BINDABLE.iter().zip(s.bindings) {
+            text.push_str(&format!("{} = {}\n", action.name(), key_value(key)));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("can not create {}: {}", dir.display(), e))?;
+        }
+        fs::write(path, text).map_err(|e| format!("can not write config {}: {}", path.display(), e))
+    }
+}
+
+mod high_scores {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::Vec2i16;
+    use crate::game_logic::{Direction, Rules};
+    use crate::settings::{self, UserDir};
+
+    // Entries kept for every category
+    pub const TOP_N: usize = 10;
+    pub const MAX_NAME_LEN: usize = 12;
+
+    // Scores only compare between games drawn the same way
+    // on the same board under the same rules
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct Category {
+        pub half_block: bool,
+        pub board: Vec2i16,
+        pub rules: Rules,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Entry {
+        pub name: String,
+        pub score: i32,
+        pub length: usize,
+        pub seconds: u64,
+        pub seed: u64,
+        // Seconds since the unix epoch
+        pub date: u64,
+    }
+
+    #[derive(Default)]
+    pub struct Table {
+        entries: Vec<(Category, Entry)>,
+    }
+
+    impl Category {
+        pub fn describe(&self) -> String {
+            format!(
+                "{}x{} {}, {}ms ticks, {} apples every {}, start {}",
+                self.board.x, self.board.y,
+                settings::mode_name(self.half_block),
+                self.rules.tick_ms,
+                self.rules.max_apples,
+                self.rules.spawn_interval,
+                self.rules.start_direction.name())
+        }
+    }
+
+    impl Table {
+        // Best entries of the category, highest score first
+        pub fn top(&self, c: &Category) -> Vec<&Entry> {
+            let mut top: Vec<&Entry> = self.entries.iter()
+                .filter(|(k, _)| k == c)
+                .map(|(_, e)| e)
+                .collect();
+
+            top.sort_by_key(|e| std::cmp::Reverse(e.score));
+            top.truncate(TOP_N);
+            top
+        }
+
+        pub fn qualifies(&self, c: &Category, score: i32) -> bool {
+            let top = self.top(c);
+
+            score > 0 && (top.len() < TOP_N || top.iter().any(|e| score > e.score))
+        }
+
+        // Add the entry, dropping whatever falls out of the category's top
+        pub fn insert(&mut self, c: Category, e: Entry) {
+            self.entries.push((c, e));
+
+            if self.entries.iter().filter(|(k, _)| *k == c).count() <= TOP_N {
+                return;
+            }
+
+            // Lowest score goes, the newest one of equal scores
+            let lowest = self.entries.iter()
+                .enumerate()
+                .filter(|(_, (k, _))| *k == c)
+                .min_by_key(|(i, (_, e))| (e.score, std::cmp::Reverse(*i)))
+                .map(|(i, _)| i);
+            if let Some(i) = lowest {
+                self.entries.remove(i);
+            }
+        }
+
+        // Every category with at least one entry
+        pub fn categories(&self) -> Vec<Category> {
+            let mut found = Vec::<Category>::new();
+
+            for (c, _) in self.entries.iter() {
+                if !found.contains(c) {
+                    found.push(*c);
+                }
+            }
+            found
+        }
+    }
+
+    pub fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    // Calendar date of a unix time, in UTC
+    pub fn date_of(secs: u64) -> String {
+        let z = (secs / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let d = doy - (153 * mp + 2) / 5 + 1;
+        let m = if mp < 10 { mp + 3 } else { mp - 9 };
+        let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02}", y, m, d)
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        settings::user_dir(UserDir::Data).map(|d| d.join("scores"))
+    }
+
+    // One entry per line, the name goes last, lines that do not
+    // parse are left out, so are ones from before rules were kept
+    pub fn load() -> Table {
+        let mut t = Table::default();
+        let text = match path().and_then(|p| fs::read_to_string(p).ok()) {
+            Some(t) => t,
+            None => return t,
+        };
+
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parsed = (|| {
+                let [mode, board, tick_ms, max_apples, spawn_interval, direction,
+                     score, length, seconds, seed, date, name] = words.as_slice() else {
+                    return None;
+                };
+                let half_block = settings::parse_mode(mode).ok()?;
+                let (w, h) = board.split_once('x')?;
+                let rules = Rules {
+                    tick_ms: tick_ms.parse().ok()?,
+                    max_apples: max_apples.parse().ok()?,
+                    spawn_interval: spawn_interval.parse().ok()?,
+                    start_direction: Direction::from_name(direction)?,
+                };
+
+                Some((
+                    Category { half_block, board: Vec2i16 { x: w.parse().ok()?, y: h.parse().ok()? }, rules },
+                    Entry {
+                        name: name.to_string(),
+                        score: score.parse().ok()?,
+                        length: length.parse().ok()?,
+                        seconds: seconds.parse().ok()?,
+                        seed: seed.parse().ok()?,
+                        date: date.parse().ok()?,
+                    }))
+            })();
+
+            if let Some((c, e)) = parsed {
+                t.insert(c, e);
+            }
+        }
+
+        t
+    }
+
+    pub fn save(t: &Table) -> Result<(), String> {
+        let path = path().ok_or("no place to keep high scores in")?;
+        let mut text = String::new();
+
+        for (c, e) in t.entries.iter() {
+            text.push_str(&format!(
+                "{} {}x{} {} {} {} {} {} {} {} {} {} {}\n",
+                settings::mode_name(c.half_block),
+                c.board.x, c.board.y,
+                c.rules.tick_ms, c.rules.max_apples, c.rules.spawn_interval, c.rules.start_direction.name(),
+                e.score, e.length, e.seconds, e.seed, e.date, e.name));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("can not create {}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, text).map_err(|e| format!("can not write high scores {}: {}", path.display(), e))
+    }
+}
+
+mod scenes {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use crate::settings::{Settings, BOARD_CHOICES, MAX_APPLES_RANGE, TICK_CHOICES};
+    use crate::high_scores::{self, Category, Table, MAX_NAME_LEN};
+    use crate::term_input::Input;
+    use crate::term_steady_out::{Handle, Render, Renderer, Text};
+    use crate::{Color, Style, Vec2i16};
+    use crate::term_input::Action;
+
+    const MENU_FRAME_MS: u64 = 50;
+
+    const TITLE_STYLE:    Style = Style { fg: Color::BrightGreen, bg: Color::Default, bold: true, underline: false };
+    const ITEM_STYLE:     Style = Style { fg: Color::White, bg: Color::Default, bold: false, underline: false };
+    const SELECTED_STYLE: Style = Style { fg: Color::Black, bg: Color::BrightGreen, bold: true, underline: false };
+    const HINT_STYLE:     Style = Style { fg: Color::BrightBlack, bg: Color::Default, bold: false, underline: false };
+
+    // Screens the program moves between
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Scene {
+        MainMenu,
+        ModeSelect,
+        Settings,
+        HighScores,
+        Play,
+        Quit,
+    }
+
+    // Title over a column of items, drawn centered with text objects
+    struct Menu {
+        title: String,
+        items: Vec<String>,
+        hint: String,
+        // Lists only show items, nothing can be selected
+        selectable: bool,
+        selected: usize,
+        texts: Vec<Handle>,
+    }
+
+    enum MenuEvent {
+        Idle,
+        Chosen(usize),
+        // Left or right pressed on the item
+        Adjust(usize, i32),
+        Back,
+    }
+
+    impl Menu {
+        pub fn initialize(title: &str, items: Vec<String>, hint: String) -> Self {
+            Menu {
+                title: title.to_string(),
+                items,
+                hint,
+                selectable: true,
+                selected: 0,
+                texts: Vec::<Handle>::new(),
+            }
+        }
+
+        // Lay the menu out again for the current terminal size
+        pub fn show(&mut self, output: &mut Renderer) {
+            self.hide(output);
+
+            let d = *output.get_terminal_dim();
+            let width = self.items.iter().map(|i| i.chars().count()).max().unwrap_or(0) + 4;
+            let mut lines = vec![(self.title.clone(), TITLE_STYLE), (String::new(), ITEM_STYLE)];
+
+            for (k, item) in self.items.iter().enumerate() {
+                let style = if self.selectable && k == self.selected { SELECTED_STYLE } else { ITEM_STYLE };
+                lines.push((format!("  {:<w$}", item, w = width - 2), style));
+            }
+            lines.push((String::new(), ITEM_STYLE));
+            lines.push((self.hint.clone(), HINT_STYLE));
+
+            let top = ((d.y - lines.len() as i16) / 2).max(0);
+            for (k, (content, style)) in lines.into_iter().enumerate() {
+                let w = content.chars().count() as i16;
+
+                self.texts.push(Text {
+                    position: Vec2i16 { x: ((d.x - w) / 2).max(0), y: top + k as i16 },
+                    content,
+                    style,
+                }.initialize(output));
+            }
+        }
+
+        pub fn hide(&mut self, output: &mut Renderer) {
+            for h in self.texts.drain(..) {
+                output.texts_mut().remove(&h);
+            }
+        }
+
+        // Up and down move the selection around, the other
+        // actions are passed on as what they mean for the menu
+        pub fn handle_action(&mut self, action: Action) -> MenuEvent {
+            let n = self.items.len().max(1);
+
+            match action {
+                Action::MoveUp => self.selected = (self.selected + n - 1) % n,
+                Action::MoveDown => self.selected = (self.selected + 1) % n,
+                Action::MoveLeft => return MenuEvent::Adjust(self.selected, -1),
+                Action::MoveRight => return MenuEvent::Adjust(self.selected, 1),
+                Action::Confirm => return MenuEvent::Chosen(self.selected),
+                Action::Quit => return MenuEvent::Back,
+                _ => {}
+            }
+            MenuEvent::Idle
+        }
+    }
+
+    // Draw the menu and wait for the next frame, returning the keys
+    // pressed meanwhile, None once the program is asked to end
+    fn frame(output: &mut Renderer, input: &Input, menu: &mut Menu) -> Option<Vec<u32>> {
+        output.update_size();
+        menu.show(output);
+        output.render();
+        sleep(Duration::from_millis(MENU_FRAME_MS));
+
+        if crate::term_backend::termination_requested() {
+            return None;
+        }
+        Some(std::iter::from_fn(|| input.pop_key()).collect())
+    }
+
+    fn hint(s: &Settings, adjust: bool) -> String {
+        let name = |a: Action| s.key_name(a);
+
+        if adjust {
+            return format!(
+                "{}/{} move  {}/{} change  enter select  {} back",
+                name(Action::MoveUp), name(Action::MoveDown),
+                name(Action::MoveLeft), name(Action::MoveRight), name(Action::Quit));
+        }
+        format!("{}/{} move  enter select  {} back", name(Action::MoveUp), name(Action::MoveDown), name(Action::Quit))
+    }
+
+    // Run a menu until an item is chosen or it is left
+    fn run_menu(
+        output: &mut Renderer,
+        input: &Input,
+        s: &Settings,
+        menu: &mut Menu) -> Option<MenuEvent> {
+        loop {
+            let keys = frame(output, input, menu)?;
+
+            for action in keys.into_iter().filter_map(|k| s.translate(k)) {
+                match menu.handle_action(action) {
+                    MenuEvent::Idle | MenuEvent::Adjust(..) => {}
+                    event => return Some(event),
+                }
+            }
+        }
+    }
+
+    pub fn run_main_menu(output: &mut Renderer, input: &Input, s: &Settings) -> Scene {
+        let items = ["play", "mode", "settings", "high scores", "quit"];
+        let mut menu = Menu::initialize("S N E A K", items.map(String::from).to_vec(), hint(s, false));
+
+        let next = match run_menu(output, input, s, &mut menu) {
+            Some(MenuEvent::Chosen(0)) => Scene::Play,
+            Some(MenuEvent::Chosen(1)) => Scene::ModeSelect,
+            Some(MenuEvent::Chosen(2)) => Scene::Settings,
+            Some(MenuEvent::Chosen(3)) => Scene::HighScores,
+            _ => Scene::Quit,
+        };
+        menu.hide(output);
+        next
+    }
+
+    // Pick how the board is drawn, cells or half blocks
+    pub fn run_mode_select(output: &mut Renderer, input: &Input, s: &mut Settings) -> Scene {
+        let items = ["cell, one pixel per character", "half block, square pixels"];
+        let mut menu = Menu::initialize("mode", items.map(String::from).to_vec(), hint(s, false));
+        menu.selected = s.half_block as usize;
+
+        let next = match run_menu(output, input, s, &mut menu) {
+            Some(MenuEvent::Chosen(k)) => {
+                s.half_block = k == 1;
+                Scene::MainMenu
+            }
+            Some(_) => Scene::MainMenu,
+            None => Scene::Quit,
+        };
+        menu.hide(output);
+        next
+    }
+
+    fn high_score_items(table: &Table, c: &Category) -> Vec<String> {
+        let top = table.top(c);
+
+        if top.is_empty() {
+            return vec!["no scores yet".to_string()];
+        }
+
+        let mut items = vec![format!(
+            "    {:<w$} {:>5} {:>6} {:>6}  {:<10}  seed",
+            "name", "score", "length", "time", "date", w = MAX_NAME_LEN)];
+        for (k, e) in top.iter().enumerate() {
+            items.push(format!(
+                "{:>2}. {:<w$} {:>5} {:>6} {:>3}:{:02}  {}  {}",
+                k + 1, e.name, e.score, e.length, e.seconds / 60, e.seconds % 60,
+                high_scores::date_of(e.date), e.seed, w = MAX_NAME_LEN));
+        }
+        items
+    }
+
+    // Table of one category at a time, left and right go through
+    // every category that has scores, starting at the current one
+    pub fn run_high_scores(output: &mut Renderer, input: &Input, s: &Settings, table: &Table) -> Scene {
+        let current = Category { half_block: s.half_block, board: s.config.board, rules: s.config.rules };
+        let mut categories = table.categories();
+        if !categories.contains(&current) {
+            categories.insert(0, current);
+        }
+        let mut shown = categories.iter().position(|c| *c == current).unwrap_or(0);
+        let n = categories.len() as i32;
+
+        let hint = format!(
+            "{}/{} category  enter or {} back",
+            s.key_name(Action::MoveLeft), s.key_name(Action::MoveRight), s.key_name(Action::Quit));
+        let mut menu = Menu::initialize("", Vec::new(), hint);
+        menu.selectable = false;
+
+        let next = 'scene: loop {
+            menu.title = format!("high scores, {}", categories[shown].describe());
+            menu.items = high_score_items(table, &categories[shown]);
+
+            let keys = match frame(output, input, &mut menu) {
+                Some(k) => k,
+                None => break Scene::Quit,
+            };
+            for action in keys.into_iter().filter_map(|k| s.translate(k)) {
+                match menu.handle_action(action) {
+                    MenuEvent::Adjust(_, by) => shown = (shown as i32 + by).rem_euclid(n) as usize,
+                    MenuEvent::Chosen(_) | MenuEvent::Back => break 'scene Scene::MainMenu,
+                    MenuEvent::Idle => {}
+                }
+            }
+        };
+        menu.hide(output);
+        next
+    }
+
+    // Choice next to the current one in the direction of by. Choices are
+    // ordered by key, a current value that is none of them goes to the
+    // nearest choice on that side, or stays if there is none.
+    pub(crate) fn step<T: Copy + PartialEq>(choices: &[T], current: T, by: i32, key: impl Fn(&T) -> i64) -> T {
+        if let Some(k) = choices.iter().position(|c| *c == current) {
+            return choices[(k as i32 + by).clamp(0, choices.len() as i32 - 1) as usize];
+        }
+
+        let ascending = choices.first().map(&key) <= choices.last().map(&key);
+        let ahead = |c: &&T| (key(c) > key(&current)) == ascending && key(c) != key(&current);
+        let next = match by > 0 {
+            true => choices.iter().find(ahead),
+            false => choices.iter().rev().find(|c| !ahead(c) && key(c) != key(&current)),
+        };
+        next.copied().unwrap_or(current)
+    }
+
+    fn settings_items(s: &Settings, capturing: Option<Action>) -> Vec<String> {
+        let mut items = vec![
+            format!("speed   {}/s", 1000 / s.config.rules.tick_ms.max(1)),
+            format!("board   {}x{}", s.config.board.x, s.config.board.y),
+            format!("apples  {}", s.config.rules.max_apples),
+        ];
+
+        for action in Action::BINDABLE {
+            let key = match capturing {
+                Some(c) if c == action => "press a key".to_string(),
+                _ => s.key_name(action),
+            };
+            items.push(format!("{:<7} {}", action.name(), key));
+        }
+        items.push("back".to_string());
+        items
+    }
+
+    // Change speed, board size and apple count with left and right,
+    // enter on an action waits for the key to bind it to
+    pub fn run_settings(output: &mut Renderer, input: &Input, s: &mut Settings) -> Scene {
+        const FIRST_BINDING: usize = 3;
+        let back = FIRST_BINDING + Action::BINDABLE.len();
+
+        let mut capturing: Option<Action> = None;
+        let mut menu = Menu::initialize("settings", settings_items(s, None), hint(s, true));
+
+        let next = 'scene: loop {
+            let keys = match frame(output, input, &mut menu) {
+                Some(k) => k,
+                None => break Scene::Quit,
+            };
+
+            for key in keys {
+                if let Some(action) = capturing.take() {
+                    s.bind(action, key);
+                    continue;
+                }
+
+                let action = match s.translate(key) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                match menu.handle_action(action) {
+                    MenuEvent::Adjust(0, by) => {
+                        s.config.rules.tick_ms = step(&TICK_CHOICES, s.config.rules.tick_ms, by, |t| *t as i64);
+                    }
+                    MenuEvent::Adjust(1, by) => {
+                        s.config.board = step(&BOARD_CHOICES, s.config.board, by, |b| b.x as i64 * b.y as i64);
+                    }
+                    MenuEvent::Adjust(2, by) => {
+                        let (low, high) = MAX_APPLES_RANGE;
+                        let apples = s.config.rules.max_apples as i64 + by as i64;
+                        s.config.rules.max_apples = apples.clamp(low as i64, high as i64) as usize;
+                    }
+                    MenuEvent::Chosen(k) if (FIRST_BINDING..back).contains(&k) => {
+                        capturing = Some(Action::BINDABLE[k - FIRST_BINDING]);
+                    }
+                    MenuEvent::Chosen(k) if k == back => break 'scene Scene::MainMenu,
+                    MenuEvent::Back => break 'scene Scene::MainMenu,
+                    _ => {}
+                }
+            }
+
+            menu.items = settings_items(s, capturing);
+            menu.hint = hint(s, true);
+        };
+        menu.hide(output);
+        next
+    }
+}
+
+mod term_input {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::Ordering;
+    #[cfg(windows)]
+    use std::thread::sleep;
+    #[cfg(windows)]
+    use std::time::Duration;
+
+    #[cfg(windows)]
+    use crate::{end_keyboard_hook_on_this_thread, set_up_keyboard_hook_on_this_thread};
+
+    // What a key means to the game and the menus once it went
+    // through the bindings, the game never sees keys themselves
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Action {
+        MoveUp,
+        MoveLeft,
+        MoveDown,
+        MoveRight,
+        Pause,
+        Quit,
+        Restart,
+        Boost,
+        Confirm,
+    }
+
+    impl Action {
+        // Actions a key can be bound to, in the order bindings are kept in
+        pub const BINDABLE: [Action; 8] = [
+            Action::MoveUp,
+            Action::MoveLeft,
+            Action::MoveDown,
+            Action::MoveRight,
+            Action::Pause,
+            Action::Quit,
+            Action::Restart,
+            Action::Boost,
+        ];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Action::MoveUp => "up",
+                Action::MoveLeft => "left",
+                Action::MoveDown => "down",
+                Action::MoveRight => "right",
+                Action::Pause => "pause",
+                Action::Quit => "quit",
+                Action::Restart => "restart",
+                Action::Boost => "boost",
+                Action::Confirm => "confirm",
+            }
+        }
+
+        pub fn from_name(name: &str) -> Option<Self> {
+            Action::BINDABLE.into_iter().chain([Action::Confirm]).find(|a| a.name() == name)
+        }
+    }
+
+    // Entries in the order they came in, oldest in front
+    pub type Queue<T> = Arc<Mutex<VecDeque<T>>>;
+    pub type KeyQueue = Queue<u32>;
+    pub type ActionQueue = Queue<Action>;
+
+    // Entries past this are dropped, so a held key can not stack up turns
+    const MAX_QUEUED: usize = 8;
+
+    pub struct Input {
+        handle: Option<std::thread::JoinHandle<()>>,
+        pub keys: KeyQueue,
+        loop_handle: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        #[cfg(unix)]
+        terminal: Option<crate::UnixInput>,
+    }
+
+    pub fn push_all<T>(queue: &Queue<T>, items: impl IntoIterator<Item = T>) {
+        let mut q = queue.lock().unwrap();
+
+        for item in items {
+            if q.len() < MAX_QUEUED {
+                q.push_back(item);
+            }
+        }
+    }
+
+    impl Input {
+        pub fn initialize() -> Self {
+            use std::thread;
+
+            let mut r = Input {
+                handle: None,
+                keys: Arc::new(Mutex::new(VecDeque::new())),
+                loop_handle: Arc::new(true.into()),
+                #[cfg(unix)]
+                terminal: crate::set_up_raw_mode_on_stdin(),
+            };
+            let loop_handle_arc = r.loop_handle.clone();
+            let keys_arc = r.keys.clone();
+
+            let h = thread::spawn(move || {
+                #[cfg(windows)]
+                {
+                    use crate::KEY_EVENTS;
+                    use winapi::um::winuser::PeekMessageA;
+                    use winapi::um::winuser::MSG;
+                    use winapi::um::winuser::PM_REMOVE;
+                    use winapi::shared::windef::HWND;
+                    use winapi::shared::windef::POINT;
+                    
+                    let mut llkbd_hook_id = set_up_keyboard_hook_on_this_thread();
+                    
+                    let mut msg = MSG {
+                        hwnd: 0 as HWND,
+                        message: 0 as u32,
+                        wParam: 0 as usize,
+                        lParam: 0 as isize,
+                        time: 0,
+                        pt: POINT { x: 0, y: 0 },
+                    };
+                    loop {
+                        unsafe {
+                            PeekMessageA(&mut msg, 0 as HWND, 0, 0, PM_REMOVE); 
+                        }
+
+                        if !loop_handle_arc.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        
+                        let pressed: Vec<u32> = KEY_EVENTS.lock().unwrap().drain(..).collect();
+                        push_all(&keys_arc, pressed);
+                        sleep(Duration::from_millis(10));
+                    }
+                    
+                    end_keyboard_hook_on_this_thread(&mut llkbd_hook_id);
+                }
+
+                #[cfg(unix)]
+                {
+                    let mut pending = Vec::new();
+
+                    while loop_handle_arc.load(Ordering::Relaxed) {
+                        push_all(&keys_arc, crate::read_keys_from_stdin(&mut pending, 10));
+                    }
+                }
+            });
+        
+            r.handle = Some(h);
+            r
+        }
+
+        // Remove and return the oldest key pressed, if any
+        pub fn pop_key(&self) -> Option<u32> {
+            self.keys.lock().unwrap().pop_front()
+        }
+
+        // Stop the input thread and give the terminal
+        // back in the state it was found in
+        pub fn destroy(&mut self) {
+            self.loop_handle.store(false, Ordering::Relaxed);
+
+            // Terminal is only given back once nothing reads from it anymore
+            if let Some(h) = self.handle.take() {
+                let _ = h.join();
+            }
+
+            #[cfg(unix)]
+            if let Some(t) = self.terminal.take() {
+                crate::end_raw_mode_on_stdin(&t);
+            }
+        }
+    }
+
+    impl Drop for Input {
+        fn drop(&mut self) {
+            self.destroy();
+        }
+    }
+}
+
+mod cli {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use crate::Vec2i16;
+    use crate::game_logic::Direction;
+    use crate::settings::{self, Settings, MAX_APPLES_RANGE, SPAWN_INTERVAL_RANGE, TICK_MS_RANGE};
+
+    pub const USAGE: &str = "\
+usage: sneak [options]
+
+  --seed N                seed the game with N instead of a random one
+  --board WxH             board size, like 40x20
+  --tick-ms N             milliseconds per game tick
+  --apples N              most apples on the board at once
+  --spawn-interval N      ticks between two apple spawns
+  --start-direction D     up, left, down or right
+  --mode M                cell or half-block
+  --half-block            same as --mode half-block
+  --config FILE           read and write settings in FILE
+  --record DIR            record every run into a file of its own in DIR
+  --replay FILE           play back a recorded run
+  --headless              run without a terminal, ends at game over
+  --stats                 print frame statistics on exit
+  -h, --help              print this help
+  -V, --version           print the version";
+
+    // Everything given on the command line, unset options are left
+    // to the config file and the defaults
+    #[derive(Default, Debug)]
+    pub struct Args {
+        pub seed: Option<u64>,
+        pub board: Option<Vec2i16>,
+        pub tick_ms: Option<u64>,
+        pub max_apples: Option<usize>,
+        pub spawn_interval: Option<u64>,
+        pub start_direction: Option<Direction>,
+        pub half_block: Option<bool>,
+        pub config: Option<PathBuf>,
+        pub record: Option<PathBuf>,
+        pub replay: Option<PathBuf>,
+        pub headless: bool,
+        pub stats: bool,
+    }
+
+    impl Args {
+        // Lay the options given over the settings, for this session only
+        pub fn apply(&self, s: &mut Settings) {
+            let rules = &mut s.config.rules;
+
+            s.config.board = self.board.unwrap_or(s.config.board);
+            s.half_block = self.half_block.unwrap_or(s.half_block);
+            rules.tick_ms = self.tick_ms.unwrap_or(rules.tick_ms);
+            rules.max_apples = self.max_apples.unwrap_or(rules.max_apples);
+            rules.spawn_interval = self.spawn_interval.unwrap_or(rules.spawn_interval);
+            rules.start_direction = self.start_direction.unwrap_or(rules.start_direction);
+        }
+
+        // Settings as they go back into the config file, values still
+        // the way the command line set them keep what the file had
+        pub fn persisted(&self, s: &Settings, file: &Settings) -> Settings {
+            let mut out = s.clone();
+            let rules = &mut out.config.rules;
+            let from = &file.config.rules;
+
+            if self.board == Some(out.config.board) {
+                out.config.board = file.config.board;
+            }
+            if self.half_block == Some(out.half_block) {
+                out.half_block = file.half_block;
+            }
+            if self.tick_ms == Some(rules.tick_ms) {
+                rules.tick_ms = from.tick_ms;
+            }
+            if self.max_apples == Some(rules.max_apples) {
+                rules.max_apples = from.max_apples;
+            }
+            if self.spawn_interval == Some(rules.spawn_interval) {
+                rules.spawn_interval = from.spawn_interval;
+            }
+            if self.start_direction == Some(rules.start_direction) {
+                rules.start_direction = from.start_direction;
+            }
+            out
+        }
+    }
+
+    pub enum Command {
+        Run(Args),
+        Help,
+        Version,
+    }
+
+    fn number(flag: &str, v: &str, range: (u64, u64)) -> Result<u64, String> {
+        let n = v.parse().map_err(|_| format!("{}: \"{}\" is not a number", flag, v))?;
+
+        settings::check_range(n, range).map_err(|e| format!("{}: {}", flag, e))
+    }
+
+    // Values other than paths are read as text
+    fn text(flag: &str, v: OsString) -> Result<String, String> {
+        v.into_string().map_err(|v| format!("{}: \"{}\" is not valid text", flag, v.to_string_lossy()))
+    }
+
+    fn set<T>(slot: &mut Option<T>, flag: &str, value: T) -> Result<(), String> {
+        if slot.replace(value).is_some() {
+            return Err(format!("{} is given twice", flag));
+        }
+        Ok(())
+    }
+
+    // Arguments without the program name, help and version end the
+    // parsing where they appear, every mistake is a usage error
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Command, String> {
+        let mut a = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let flag = flag.into_string()
+                .map_err(|f| format!("unexpected argument \"{}\"", f.to_string_lossy()))?;
+            let flag = flag.as_str();
+            let mut value = || args.next().ok_or(format!("{} needs a value", flag));
+
+            match flag {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-V" | "--version" => return Ok(Command::Version),
+                "--seed" => {
+                    let v = text(flag, value()?)?;
+                    let n = v.parse().map_err(|_| format!("--seed: \"{}\" is not a number", v))?;
+                    set(&mut a.seed, flag, n)?;
+                }
+                "--board" => {
+                    let b = settings::parse_board(&text(flag, value()?)?).map_err(|e| format!("--board: {}", e))?;
+                    set(&mut a.board, flag, b)?;
+                }
+                "--tick-ms" => set(&mut a.tick_ms, flag, number(flag, &text(flag, value()?)?, TICK_MS_RANGE)?)?,
+                "--apples" => set(&mut a.max_apples, flag, number(flag, &text(flag, value()?)?, MAX_APPLES_RANGE)? as usize)?,
+                "--spawn-interval" => {
+                    set(&mut a.spawn_interval, flag, number(flag, &text(flag, value()?)?, SPAWN_INTERVAL_RANGE)?)?;
+                }
+                "--start-direction" => {
+                    let v = text(flag, value()?)?;
+                    let d = Direction::from_name(&v)
+                        .ok_or(format!("--start-direction: \"{}\" is not one of up, left, down, right", v))?;
+                    set(&mut a.start_direction, flag, d)?;
+                }
+                "--mode" => {
+                    let half_block = settings::parse_mode(&text(flag, value()?)?)
+                        .map_err(|e| format!("--mode: {}", e))?;
+                    set(&mut a.half_block, flag, half_block)?;
+                }
+                "--half-block" => set(&mut a.half_block, flag, true)?,
+                "--config" => set(&mut a.config, flag, PathBuf::from(value()?))?,
+                "--record" => set(&mut a.record, flag, PathBuf::from(value()?))?,
+                "--replay" => set(&mut a.replay, flag, PathBuf::from(value()?))?,
+                "--headless" => a.headless = true,
+                "--stats" => a.stats = true,
+                other if other.starts_with('-') => return Err(format!("unknown option {}", other)),
+                other => return Err(format!("unexpected argument \"{}\"", other)),
+            }
+        }
+
+        // A replay brings the whole setup of its run along
+        if a.replay.is_some() {
+            let conflicting = [
+                ("--seed", a.seed.is_some()),
+                ("--board", a.board.is_some()),
+                ("--tick-ms", a.tick_ms.is_some()),
+                ("--apples", a.max_apples.is_some()),
+                ("--spawn-interval", a.spawn_interval.is_some()),
+                ("--start-direction", a.start_direction.is_some()),
+                ("--mode", a.half_block.is_some()),
+                ("--record", a.record.is_some()),
+            ];
+            if let Some((flag, _)) = conflicting.iter().find(|c| c.1) {
+                return Err(format!("{} can not be used with --replay", flag));
+            }
+        }
+
+        Ok(Command::Run(a))
+    }
+}
+
+// What the runs of one program session share
+struct Session {
+    table: high_scores::Table,
+    // Where runs are recorded, "--record DIR" or sneak-replays in the temp directory
+    record_dir: std::path::PathBuf,
+    // Replay file of the run recorded last
+    last_record: Option<std::path::PathBuf>,
+    errors: Vec<String>,
+}
+
+// Record a finished or abandoned run to a replay file of its own, named
+// after when it ended and its seed. The header only differs from the
+// first run's in the seed a restart drew.
+fn save_run(session: &mut Session, header: &replay::Header, g: &game_logic::Game) {
+    let header = replay::Header { seed: g.get_seed(), ..header.clone() };
+    let dir = &session.record_dir;
+
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        session.errors.push(format!("can not create {}: {}", dir.display(), e));
+        return;
+    }
+
+    let stem = format!("sneak-{}-{}", high_scores::now(), header.seed);
+    let path = (1..)
+        .map(|k| match k {
+            1 => dir.join(format!("{}.replay", stem)),
+            k => dir.join(format!("{}-{}.replay", stem, k)),
+        })
+        .find(|p| !p.exists())
+        .unwrap_or_default();
+
+    match replay::save(&path, &header, g.get_recording(), g.get_tick(), g.get_score()) {
+        Ok(()) => session.last_record = Some(path),
+        Err(e) => session.errors.push(format!("can not write replay {}: {}", path.display(), e)),
+    }
+}
+
+// How a game went, kept for the summary printed on exit
+struct RunSummary {
+    score: i32,
+    seed: u64,
+    won: bool,
+    tick: u64,
+    frames: usize,
+    frame_bytes: usize,
+}
+
+// What a game played with the settings is set up with,
+// a fresh random seed unless one was given
+fn header_from(s: &settings::Settings, seed: Option<u64>) -> replay::Header {
+    replay::Header {
+        seed: seed.unwrap_or_else(rand::random),
+        half_block: s.half_block,
+        config: s.config,
+    }
+}
+
+// Play games on the renderer until the player leaves or, for a replay,
+// until its run is over. Keys reach the game through the bindings.
+// Without input the game runs headless, as fast as it can and only
+// until its first game over.
+fn run_game(
+    x: &mut term_steady_out::Renderer,
+    i: Option<&term_input::Input>,
+    s: &settings::Settings,
+    header: &replay::Header,
+    mut playback: Option<&mut replay::Replay>,
+    session: &mut Session) -> RunSummary {
+    use std::sync::{Arc, Mutex};
+    use term_steady_out::Render;
+    use term_steady_out::PixelMode;
+    use game_logic::{Game, State};
+    use high_scores::{Category, Entry};
+    use term_input::Action;
+
+    x.set_pixel_mode(if header.half_block { PixelMode::HalfBlock } else { PixelMode::Cell });
+
+    // A replay drives the game on its own,
+    // the keyboard is only watched for quit to stop watching
+    let actions: term_input::ActionQueue = Arc::new(Mutex::new(Default::default()));
+    let mut g = Game::initialize(x, actions.clone(), header.config, header.seed);
+    g.set_key_names([Action::Pause, Action::Quit, Action::Restart].map(|a| s.key_name(a)));
+    let replaying = playback.is_some();
+    let headless = i.is_none();
+    let mode = |g: &Game| match g.state {
+        State::Paused => "paused",
+        State::GameOver => "game over",
+        _ if replaying => "replay",
+        _ => "play",
+    };
+    g.update_hud(x, mode(&g));
+    g.fit_to_screen(x);
+    let mut frames: usize = 0;
+    let mut frame_bytes: usize = 0;
+    // Every run is recorded once, when it is over
+    let mut run_saved = false;
+    let category = Category { half_block: header.half_block, board: header.config.board, rules: header.config.rules };
+    // Name typed in on the game over screen while a score makes the table
+    let mut naming: Option<String> = None;
+    let mut prompt: Option<term_steady_out::Handle> = None;
+
+    loop
+    {
+        if !headless {
+            sleep(Duration::from_millis(header.config.rules.tick_ms));
+        }
+
+        if x.update_size() {
+            g.fit_to_screen(x);
+        }
+        let pressed: Vec<u32> = match i {
+            Some(i) => std::iter::from_fn(|| i.pop_key()).collect(),
+            None => Vec::new(),
+        };
+        let pressed = match &mut naming {
+            Some(name) => {
+                if type_name(name, pressed) {
+                    session.table.insert(category, Entry {
+                        name: if name.is_empty() { "anonymous".to_string() } else { name.clone() },
+                        score: g.get_score(),
+                        length: g.get_length(),
+                        seconds: g.get_seconds(),
+                        seed: g.get_seed(),
+                        date: high_scores::now(),
+                    });
+                    session.errors.extend(high_scores::save(&session.table).err());
+                    naming = None;
+                }
+                Vec::new()
+            }
+            None => pressed,
+        };
+        let pressed = pressed.into_iter().filter_map(|k| s.translate(k));
+        match &mut playback {
+            Some(r) => {
+                r.feed(g.get_tick(), &actions);
+
+                if pressed.into_iter().any(|a| a == Action::Quit) {
+                    break;
+                }
+            }
+            None => term_input::push_all(&actions, pressed),
+        }
+        g.update(x);
+        show_name_prompt(x, &mut prompt, naming.as_deref());
+        g.update_hud(x, mode(&g));
+        x.render();
+        frames += 1;
+        frame_bytes += x.get_frame_bytes();
+
+        match g.state {
+            State::GameOver if !run_saved => {
+                run_saved = true;
+                if !replaying {
+                    save_run(session, header, &g);
+                }
+                if !replaying && !headless && session.table.qualifies(&category, g.get_score()) {
+                    naming = Some(String::new());
+                }
+            }
+            State::Running => run_saved = false,
+            _ => {}
+        }
+
+        // A replay holds a single run, it ends with it
+        if g.state == State::Quit ||
+            ((replaying || headless) && g.state == State::GameOver) ||
+            term_backend::termination_requested() {
+            break;
+        }
+    };
+
+    if !run_saved && !replaying {
+        save_run(session, header, &g);
+    }
+    show_name_prompt(x, &mut prompt, None);
+    g.destroy(x);
+    x.render();
+
+    RunSummary {
+        score: g.get_score(),
+        seed: g.get_seed(),
+        won: g.won,
+        tick: g.get_tick(),
+        frames,
+        frame_bytes,
+    }
+}
+
+// Letters and digits go into the name, backspace takes one out,
+// return true once enter finishes it
+fn type_name(name: &mut String, keys: Vec<u32>) -> bool {
+    for key in keys {
+        let c = match u8::try_from(key) {
+            Ok(ENTER_KEY) => return true,
+            Ok(BACKSPACE_KEY) => {
+                name.pop();
+                continue;
+            }
+            Ok(k) if k.is_ascii_uppercase() || k.is_ascii_digit() => k as char,
+            Ok(k) if (NUMPAD_0_KEY..NUMPAD_0_KEY + 10).contains(&k) => (b'0' + k - NUMPAD_0_KEY) as char,
+            _ => continue,
+        };
+        if name.len() < high_scores::MAX_NAME_LEN {
+            name.push(c);
+        }
+    }
+    false
+}
+
+// Name being typed in, centered two lines under the game over overlay
+fn show_name_prompt(
+    x: &mut term_steady_out::Renderer,
+    prompt: &mut Option<term_steady_out::Handle>,
+    name: Option<&str>) {
+    const PROMPT_STYLE: Style = Style { fg: Color::BrightYellow, bg: Color::Default, bold: true, underline: false };
+
+    if let Some(h) = prompt.take() {
+        x.texts_mut().remove(&h);
+    }
+
+    let name = match name {
+        Some(n) => n,
+        None => return,
+    };
+    let content = format!(" new high score, name: {}_  enter to save ", name);
+    let d = *x.get_terminal_dim();
+    let width = content.chars().count() as i16;
+
+    *prompt = Some(term_steady_out::Text {
+        position: Vec2i16 { x: ((d.x - width) / 2).max(0), y: d.y / 2 + 2 },
+        content,
+        style: PROMPT_STYLE,
+    }.initialize(x));
+}
+
+fn main() {
+    use term_steady_out::Renderer;
+    use term_input::Input;
+    use scenes::Scene;
+
+    let args = match cli::parse(std::env::args_os().skip(1)) {
+        Ok(cli::Command::Run(a)) => a,
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(cli::Command::Version) => {
+            println!("sneak {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("sneak: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut playback = match args.replay.as_deref().map(replay::load) {
+        Some(Ok(r)) => Some(r),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let config_path = args.config.clone().or_else(settings::path);
+    let loaded = match &config_path {
+        Some(p) => settings::load(p, args.config.is_some()),
+        None => Ok(settings::Settings::default()),
+    };
+    // Settings as the file has them, the command line only lasts a session
+    let file = match loaded {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut s = file.clone();
+    args.apply(&mut s);
+    let header = match &playback {
+        Some(r) => r.header.clone(),
+        None => header_from(&s, args.seed),
+    };
+
+    // Headless runs draw into a screen of their own that fits the board
+    let mut x = if args.headless {
+        let board = header.config.board;
+        let size = Vec2i16 { x: board.x + 2, y: board.y + 4 };
+
+        Renderer::initialize(Box::new(term_backend::VirtualBackend::initialize(size)))
+    } else {
+        Renderer::initialize(term_backend::default_backend())
+    };
+    let mut i = if args.headless { None } else { Some(Input::initialize()) };
+    let mut last: Option<RunSummary> = None;
+    let mut session = Session {
+        table: high_scores::load(),
+        record_dir: args.record.clone().unwrap_or_else(|| std::env::temp_dir().join("sneak-replays")),
+        last_record: None,
+        errors: Vec::new(),
+    };
+
+    match (i.as_ref(), playback.as_mut()) {
+        (i, Some(r)) => {
+            last = Some(run_game(&mut x, i, &s, &header, Some(r), &mut session));
+        }
+        (None, None) => {
+            last = Some(run_game(&mut x, None, &s, &header, None, &mut session));
+        }
+        (Some(i), None) => {
+            let mut scene = Scene::MainMenu;
+
+            while scene != Scene::Quit {
+                scene = match scene {
+                    Scene::MainMenu => scenes::run_main_menu(&mut x, i, &s),
+                    Scene::ModeSelect | Scene::Settings => {
+                        let before = s.clone();
+                        let next = if scene == Scene::ModeSelect {
+                            scenes::run_mode_select(&mut x, i, &mut s)
+                        } else {
+                            scenes::run_settings(&mut x, i, &mut s)
+                        };
+
+                        match &config_path {
+                            _ if s == before => {}
+                            Some(p) => session.errors.extend(settings::save(&args.persisted(&s, &file), p).err()),
+                            None => session.errors.push("no place to keep settings in".to_string()),
+                        }
+                        next
+                    }
+                    Scene::HighScores => scenes::run_high_scores(&mut x, i, &s, &session.table),
+                    Scene::Play => {
+                        let header = header_from(&s, args.seed);
+                        last = Some(run_game(&mut x, Some(i), &s, &header, None, &mut session));
+
+                        if term_backend::termination_requested() { Scene::Quit } else { Scene::MainMenu }
+                    }
+                    Scene::Quit => Scene::Quit,
+                };
+            }
+        }
+    }
+
+    if let Some(i) = &mut i {
+        i.destroy();
+    }
+    x.destroy();
+
+    if let Some(run) = &last {
+        if run.won {
+            println!("board cleared");
+        }
+        println!("score {}", run.score);
+        println!("seed {}", run.seed);
+        if let Some(path) = &session.last_record {
+            println!("replay {}", path.display());
+        }
+        if let Some(r) = &playback {
+            println!("recorded score {} at tick {}, replayed to tick {}", r.score, r.end_tick, run.tick);
+        }
+        if args.stats {
+            println!("frames {} bytes {} per frame {}", run.frames, run.frame_bytes, run.frame_bytes / run.frames.max(1));
+        }
+    }
+    for e in session.errors {
+        eprintln!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::term_backend::{VirtualBackend, VirtualScreen};
+    use crate::term_steady_out::{Handle, MashedPixels, Render, Renderer, Text};
+    use crate::{Color, Square, Style, Vec2i16};
+
+    const RED: Style = Style { fg: Color::Red, bg: Color::Default, bold: false, underline: false };
+
+    fn renderer(x: i16, y: i16) -> (Renderer, Rc<RefCell<VirtualScreen>>) {
+        let backend = VirtualBackend::initialize(Vec2i16 { x, y });
+        let screen = backend.get_screen();
+
+        (Renderer::initialize(Box::new(backend)), screen)
+    }
+
+    fn object(r: &mut Renderer, x: i16, y: i16, w: i16, h: i16, glyph: char) -> Handle {
+        MashedPixels {
+            sqare: Square { position: Vec2i16 { x, y }, size: Vec2i16 { x: w, y: h } },
+            glyph,
+            style: RED,
+        }.initialize(r)
+    }
+
+    fn text(r: &mut Renderer, x: i16, y: i16, content: &str) -> Handle {
+        Text { position: Vec2i16 { x, y }, content: content.to_string(), style: Style::default() }.initialize(r)
+    }
+
+    #[test]
+    fn virtual_screen_applies_cursor_moves_and_styles() {
+        let mut screen = VirtualScreen::initialize(Vec2i16 { x: 6, y: 2 });
+
+        screen.write(b"ab\x1b[2;3H\x1b[0;31mc");
+
+        assert_eq!(screen.get_text(), "ab    \n  c   ");
+        assert_eq!(*screen.get_cursor_pos(), Vec2i16 { x: 3, y: 1 });
+        assert_eq!(screen.get_char(Vec2i16 { x: 2, y: 1 }), Some('c'));
+        assert_eq!(screen.get_style(Vec2i16 { x: 2, y: 1 }).map(|s| s.fg), Some(Color::Red));
+        assert_eq!(screen.get_char(Vec2i16 { x: 6, y: 0 }), None);
+    }
+
+    #[test]
+    fn diff_frame_rewrites_only_what_changed() {
+        let (mut r, screen) = renderer(12, 3);
+        let h = object(&mut r, 1, 1, 2, 1, '#');
+        text(&mut r, 8, 0, "hi");
+
+        r.render();
+        let full = r.get_frame_bytes();
+        assert_eq!(screen.borrow().get_text(), "        hi  \n ##         \n            ");
+
+        r.objects_mut().get_mut(&h).unwrap().sqare.position.x = 5;
+        r.render();
+        assert_eq!(screen.borrow().get_text(), "        hi  \n     ##     \n            ");
+        assert!(r.get_frame_bytes() < full);
+
+        r.render();
+        assert_eq!(r.get_frame_bytes(), 0);
+    }
+
+    #[test]
+    fn repaint_after_resize_is_counted_in_its_frame() {
+        use crate::term_steady_out::PixelMode;
+
+        let (mut r, screen) = renderer(20, 9);
+        r.set_pixel_mode(PixelMode::HalfBlock);
+        object(&mut r, 2, 2, 3, 3, '#');
+
+        // A blank repaint of all rows but the last alone takes a byte a cell
+        r.render();
+        assert!(r.get_frame_bytes() > 20 * 8);
+
+        screen.borrow_mut().resize(Vec2i16 { x: 30, y: 9 });
+        r.render();
+        assert!(r.get_frame_bytes() > 30 * 8);
+    }
+
+    #[test]
+    fn diff_frame_keeps_column_after_wide_glyph() {
+        let (mut r, screen) = renderer(20, 3);
+        let wide = text(&mut r, 2, 0, "本");
+        let narrow = text(&mut r, 10, 0, "x");
+        r.render();
+
+        r.texts_mut().get_mut(&wide).unwrap().content = "日".to_string();
+        r.texts_mut().get_mut(&narrow).unwrap().content = "y".to_string();
+        r.render();
+
+        assert_eq!(screen.borrow().get_line(0), "  日      y         ");
+    }
+
+    #[test]
+    fn whole_screen_is_repainted_after_resize() {
+        let (mut r, screen) = renderer(8, 3);
+        object(&mut r, 0, 0, 1, 1, '#');
+        r.render();
+
+        // Whatever a resized terminal shows is not trusted
+        screen.borrow_mut().write(b"\x1b[2;1Hjunk");
+        screen.borrow_mut().resize(Vec2i16 { x: 10, y: 4 });
+        r.render();
+
+        assert_eq!(screen.borrow().get_text(), "#         \n          \n          \n          ");
+        assert_eq!(*r.get_terminal_dim(), Vec2i16 { x: 10, y: 4 });
+    }
+
+    #[test]
+    fn whole_screen_paint_rewrites_a_trashed_screen() {
+        let (mut r, screen) = renderer(8, 3);
+        object(&mut r, 1, 1, 2, 1, '#');
+        r.render();
+
+        screen.borrow_mut().write(b"\x1b[1;1Hjunk\x1b[2;1Hjunk");
+        r.paint_whole_screen_in_letter_a();
+
+        assert_eq!(screen.borrow().get_text(), "        \n ##     \n        ");
+    }
+
+    #[test]
+    fn objects_are_clipped_at_every_edge() {
+        let (mut r, screen) = renderer(6, 4);
+        object(&mut r, -2, 1, 3, 1, 'l');
+        object(&mut r, 4, 2, 5, 1, 'r');
+        object(&mut r, 2, -3, 1, 4, 't');
+        object(&mut r, 3, 3, 1, 9, 'b');
+        object(&mut r, -9, -9, 3, 3, 'x');
+        text(&mut r, 4, 0, "long");
+        r.render();
+
+        // The bottom right cell is never written
+        assert_eq!(screen.borrow().get_text(), "  t lo\nl     \n    rr\n   b  ");
+    }
+
+    #[test]
+    fn config_comments_are_stripped_outside_strings() {
+        let s = crate::settings::parse("mode = \"half-block\" # or cell\n# whole line\nboard = \"30x15\"#tight\n").unwrap();
+
+        assert!(s.half_block);
+        assert_eq!(s.config.board, Vec2i16 { x: 30, y: 15 });
+        assert!(crate::settings::parse("mode = \"cell # not a comment\"").is_err());
+    }
+
+    #[test]
+    fn config_sets_every_tunable() {
+        use crate::game_logic::Direction;
+        use crate::term_input::Action;
+
+        let s = crate::settings::parse(
+            "tick_ms = 75\nboard = \"20x10\"\nmax_apples = 3\nspawn_interval = 7\n\
+             start_direction = \"left\"\nmode = \"cell\"\n\n[keys]\nup = \"i\"\nboost = \"num5\"\nquit = 27\n").unwrap();
+        let rules = s.config.rules;
+
+        assert_eq!((rules.tick_ms, rules.max_apples, rules.spawn_interval), (75, 3, 7));
+        assert_eq!(rules.start_direction, Direction::Left);
+        assert_eq!(s.config.board, Vec2i16 { x: 20, y: 10 });
+        assert_eq!(s.translate('I' as u32), Some(Action::MoveUp));
+        assert_eq!(s.translate('W' as u32), None);
+        assert_eq!(s.key_name(Action::Boost), "num5");
+        assert_eq!(s.translate(27), Some(Action::Quit));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escape_sequences_decode_to_keys() {
+        use crate::{decode_keys, DOWN_KEY, ENTER_KEY, LEFT_KEY, NUMPAD_0_KEY, RIGHT_KEY, UP_KEY};
+
+        let decode = |bytes: &[u8]| decode_keys(&mut Vec::new(), bytes);
+        let keys = |ks: &[u8]| ks.iter().map(|k| *k as u32).collect::<Vec<_>>();
+
+        assert_eq!(decode(b"\x1b[A\x1b[B\x1b[C\x1b[D"), keys(&[UP_KEY, DOWN_KEY, RIGHT_KEY, LEFT_KEY]));
+        assert_eq!(decode(b"\x1bOA\x1bOB\x1bOC\x1bOD"), keys(&[UP_KEY, DOWN_KEY, RIGHT_KEY, LEFT_KEY]));
+        assert_eq!(decode(b"\x1b[1;5Cw\x1b[2~q"), keys(&[RIGHT_KEY, b'W', b'Q']));
+        assert_eq!(decode(b"\x1bOp\x1bOt\x1bOy\x1bOM"),
+                   keys(&[NUMPAD_0_KEY, NUMPAD_0_KEY + 4, NUMPAD_0_KEY + 9, ENTER_KEY]));
+        assert_eq!(decode(b"\x1bq"), keys(&[27, b'Q']));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escape_sequences_split_over_reads_wait_for_their_end() {
+        use crate::{decode_keys, decode_lone_escape, LEFT_KEY, NUMPAD_0_KEY, UP_KEY};
+
+        let mut pending = Vec::new();
+
+        assert_eq!(decode_keys(&mut pending, b"a\x1b"), ['A' as u32]);
+        assert_eq!(decode_keys(&mut pending, b"[A"), [UP_KEY as u32]);
+        assert_eq!(decode_keys(&mut pending, b"\x1b[1;"), []);
+        assert_eq!(decode_keys(&mut pending, b"5D"), [LEFT_KEY as u32]);
+        assert_eq!(decode_keys(&mut pending, b"\x1bO"), []);
+        assert_eq!(decode_keys(&mut pending, b"r"), [NUMPAD_0_KEY as u32 + 2]);
+        assert!(pending.is_empty());
+
+        // An escape nothing follows is the escape key
+        assert_eq!(decode_keys(&mut pending, b"\x1b"), []);
+        assert_eq!(decode_lone_escape(&mut pending), [27]);
+        assert_eq!(decode_lone_escape(&mut pending), []);
+        assert_eq!(decode_keys(&mut pending, b"\x1b["), []);
+        assert_eq!(decode_lone_escape(&mut pending), []);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn arrows_vi_keys_and_numpad_move_besides_the_bindings() {
+        use crate::term_input::Action;
+        use crate::{DOWN_KEY, ENTER_KEY, LEFT_KEY, NUMPAD_0_KEY, RIGHT_KEY, UP_KEY};
+
+        let mut s = crate::settings::Settings::default();
+        let moves = |s: &crate::settings::Settings, ks: [u8; 4]| ks.map(|k| s.translate(k as u32));
+        let expected = [Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight].map(Some);
+
+        assert_eq!(moves(&s, [b'W', b'A', b'S', b'D']), expected);
+        assert_eq!(moves(&s, [UP_KEY, LEFT_KEY, DOWN_KEY, RIGHT_KEY]), expected);
+        assert_eq!(moves(&s, [b'K', b'H', b'J', b'L']), expected);
+        assert_eq!(moves(&s, [8, 4, 2, 6].map(|d| NUMPAD_0_KEY + d)), expected);
+        assert_eq!(s.translate(ENTER_KEY as u32), Some(Action::Confirm));
+        assert_eq!(s.translate(NUMPAD_0_KEY as u32 + 5), None);
+
+        // A bound key means its action rather than the alternate one
+        s.bind(Action::Pause, b'K' as u32);
+        assert_eq!(s.translate(b'K' as u32), Some(Action::Pause));
+        assert_eq!(s.translate(b'J' as u32), Some(Action::MoveDown));
+    }
+
+    #[test]
+    fn mode_names_read_back_everywhere_the_same() {
+        use crate::settings::{mode_name, parse_mode};
+
+        for half_block in [false, true] {
+            assert_eq!(parse_mode(mode_name(half_block)), Ok(half_block));
+        }
+        assert_eq!(parse_mode("half block"), Err("\"half block\" is not one of cell, half-block".to_string()));
+        assert_eq!(parse_args("--mode wide").err().unwrap(), "--mode: \"wide\" is not one of cell, half-block");
+        assert_eq!(crate::settings::parse("mode = \"wide\"").unwrap_err().1, "mode: \"wide\" is not one of cell, half-block");
+    }
+
+    #[test]
+    fn config_errors_point_at_the_line() {
+        let error = |text: &str| crate::settings::parse(text).unwrap_err();
+
+        assert_eq!(error("\ntick_ms = 3"), (2, "tick_ms: 3 is out of range 10..=5000".to_string()));
+        assert_eq!(error("board = \"1x5\"").0, 1);
+        assert_eq!(error("speed = 1"), (1, "speed: unknown setting".to_string()));
+        assert_eq!(error("[sound]"), (1, "unknown table [sound]".to_string()));
+        assert_eq!(error("tick_ms = 50\ntick_ms = 60"), (2, "tick_ms is set twice".to_string()));
+        assert_eq!(error("[keys]\nup = \"A\""), (2, "keys: up and left are both bound to A".to_string()));
+        assert_eq!(error("[keys]\nup = 13"), (2, "keys.up: enter can not be bound".to_string()));
+        assert_eq!(error("mode = cell"), (1, "malformed value for mode".to_string()));
+        assert_eq!(error("tick_ms"), (1, "expected name = value".to_string()));
+    }
+
+    #[test]
+    fn saved_config_reads_back_the_same() {
+        use crate::term_input::Action;
+
+        let mut s = crate::settings::Settings { half_block: true, ..Default::default() };
+        s.config.board = Vec2i16 { x: 33, y: 12 };
+        s.bind(Action::MoveUp, crate::UP_KEY as u32);
+        s.bind(Action::Pause, 27);
+
+        let path = std::env::temp_dir().join(format!("sneak-test-config-{}.toml", std::process::id()));
+        crate::settings::save(&s, &path).unwrap();
+        let back = crate::settings::load(&path, true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(back.config, s.config);
+        assert_eq!(back.half_block, s.half_block);
+        assert_eq!(back.bindings, s.bindings);
+    }
+
+    fn parse_args(args: &str) -> Result<crate::cli::Command, String> {
+        crate::cli::parse(args.split_whitespace().map(std::ffi::OsString::from))
+    }
+
+    fn run_args(args: &str) -> crate::cli::Args {
+        match parse_args(args) {
+            Ok(crate::cli::Command::Run(a)) => a,
+            _ => panic!("{} did not parse into a run", args),
+        }
+    }
+
+    #[test]
+    fn cli_reads_every_option() {
+        use crate::game_logic::Direction;
+
+        let a = run_args(
+            "--seed 9 --board 30x12 --tick-ms 80 --apples 0 --spawn-interval 4 --start-direction down \
+             --mode half-block --config c.toml --record runs --headless --stats");
+
+        assert_eq!(a.seed, Some(9));
+        assert_eq!(a.board, Some(Vec2i16 { x: 30, y: 12 }));
+        assert_eq!((a.tick_ms, a.max_apples, a.spawn_interval), (Some(80), Some(0), Some(4)));
+        assert_eq!(a.start_direction, Some(Direction::Down));
+        assert_eq!(a.half_block, Some(true));
+        assert_eq!(a.config, Some("c.toml".into()));
+        assert_eq!(a.record, Some("runs".into()));
+        assert!(a.headless && a.stats);
+        assert!(parse_args("--board 1x1 --help").is_err());
+        assert!(matches!(parse_args("--stats --help --bogus"), Ok(crate::cli::Command::Help)));
+        assert!(matches!(parse_args("-V"), Ok(crate::cli::Command::Version)));
+    }
+
+    #[test]
+    fn cli_mistakes_are_usage_errors() {
+        let error = |args: &str| parse_args(args).err().unwrap();
+
+        assert_eq!(error("--bogus"), "unknown option --bogus");
+        assert_eq!(error("play"), "unexpected argument \"play\"");
+        assert_eq!(error("--seed"), "--seed needs a value");
+        assert_eq!(error("--seed x"), "--seed: \"x\" is not a number");
+        assert_eq!(error("--board 0x5"), "--board: sides must be in range 2..=1000");
+        assert_eq!(error("--tick-ms 2"), "--tick-ms: 2 is out of range 10..=5000");
+        assert_eq!(error("--mode wide"), "--mode: \"wide\" is not one of cell, half-block");
+        assert_eq!(error("--seed 1 --seed 2"), "--seed is given twice");
+        assert_eq!(error("--half-block --mode cell"), "--mode is given twice");
+        assert_eq!(error("--replay r --board 20x10"), "--board can not be used with --replay");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cli_takes_any_path_but_only_text_values() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let parse = |args: [&[u8]; 2]| crate::cli::parse(args.map(|a| OsString::from_vec(a.to_vec())));
+
+        match parse([b"--config", b"\xff.toml"]) {
+            Ok(crate::cli::Command::Run(a)) => assert_eq!(a.config, Some(OsString::from_vec(b"\xff.toml".to_vec()).into())),
+            _ => panic!("a path that is not text did not parse"),
+        }
+        assert_eq!(parse([b"--board", b"\xffx5"]).err().unwrap(), "--board: \"\u{fffd}x5\" is not valid text");
+        assert_eq!(parse([b"--\xff", b"x"]).err().unwrap(), "unexpected argument \"--\u{fffd}\"");
+    }
+
+    #[test]
+    fn cli_overrides_last_a_session_only() {
+        let a = run_args("--board 30x12 --tick-ms 80");
+        let file = crate::settings::Settings::default();
+        let mut s = file.clone();
+
+        a.apply(&mut s);
+        assert_eq!(s.config.board, Vec2i16 { x: 30, y: 12 });
+        assert_eq!(s.config.rules.tick_ms, 80);
+
+        // Changed in the menus after, so it is kept
+        s.config.rules.tick_ms = 150;
+        let saved = a.persisted(&s, &file);
+        assert_eq!(saved.config.board, file.config.board);
+        assert_eq!(saved.config.rules.tick_ms, 150);
+    }
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sneak-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn replay_reads_back_what_was_saved() {
+        use std::sync::{Arc, Mutex};
+        use crate::game_logic::{Direction, GameConfig, Rules};
+        use crate::replay::{self, Header};
+        use crate::term_input::{Action, ActionQueue};
+
+        let header = Header {
+            seed: 42,
+            half_block: true,
+            config: GameConfig {
+                board: Vec2i16 { x: 25, y: 11 },
+                rules: Rules { tick_ms: 90, max_apples: 3, spawn_interval: 5, start_direction: Direction::Right },
+            },
+        };
+        let events = [(0, Action::MoveUp), (0, Action::Boost), (7, Action::Pause), (9, Action::Pause)];
+        let path = temp_file("round-trip.replay");
+
+        replay::save(&path, &header, &events, 12, 4).unwrap();
+        let mut r = replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(r.header, header);
+        assert_eq!((r.end_tick, r.score), (12, 4));
+
+        let queue: ActionQueue = Arc::new(Mutex::new(Default::default()));
+        r.feed(0, &queue);
+        assert_eq!(queue.lock().unwrap().drain(..).collect::<Vec<_>>(), [Action::MoveUp, Action::Boost]);
+        r.feed(8, &queue);
+        assert_eq!(queue.lock().unwrap().drain(..).collect::<Vec<_>>(), [Action::Pause]);
+    }
+
+    #[test]
+    fn replay_headers_out_of_range_are_refused() {
+        let path = temp_file("bad.replay");
+        let load = |board: &str, rules: &str| {
+            std::fs::write(&path, format!(
+                "sneak-replay 5\nseed 1\nboard {}\nmode cell\nrules {}\nend 0 0\n", board, rules)).unwrap();
+            crate::replay::load(&path).map(|_| ())
+        };
+
+        assert!(load("20x10", "100 12 20 up").is_ok());
+        assert!(load("-5x3", "100 12 20 up").is_err());
+        assert!(load("30000x30000", "100 12 20 up").is_err());
+        assert!(load("20x10", "1 12 20 up").is_err());
+        assert!(load("20x10", "100 99999 20 up").is_err());
+        assert!(load("20x10", "100 12 0 up").is_err());
+        assert!(load("20x10", "100 12 20 north").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn high_scores_keep_rules_apart() {
+        use crate::game_logic::{Rules, DEFAULT_RULES};
+        use crate::high_scores::{Category, Entry, Table};
+
+        let normal = Category { half_block: false, board: Vec2i16 { x: 40, y: 20 }, rules: DEFAULT_RULES };
+        let farmed = Category { rules: Rules { tick_ms: 5000, max_apples: 10000, spawn_interval: 1, ..DEFAULT_RULES }, ..normal };
+        let entry = |score| Entry { name: "n".to_string(), score, length: 1, seconds: 1, seed: 1, date: 0 };
+
+        let mut t = Table::default();
+        t.insert(farmed, entry(500));
+        t.insert(normal, entry(7));
+
+        assert_eq!(t.top(&normal).iter().map(|e| e.score).collect::<Vec<_>>(), [7]);
+        assert_eq!(t.top(&farmed).iter().map(|e| e.score).collect::<Vec<_>>(), [500]);
+        assert_eq!(farmed.describe(), "40x20 cell, 5000ms ticks, 10000 apples every 1, start up");
+    }
+
+    #[test]
+    fn menu_steps_snap_to_the_nearest_choice() {
+        let tick = |t: u64, by| crate::scenes::step(&crate::settings::TICK_CHOICES, t, by, |t| *t as i64);
+        assert_eq!(tick(150, 1), 100);
+        assert_eq!(tick(50, 1), 50);
+        assert_eq!(tick(120, 1), 100);
+        assert_eq!(tick(120, -1), 150);
+        assert_eq!(tick(10, 1), 10);
+        assert_eq!(tick(10, -1), 50);
+
+        let board = |x, y, by| crate::scenes::step(&crate::settings::BOARD_CHOICES, Vec2i16 { x, y }, by, |b| b.x as i64 * b.y as i64);
+        assert_eq!(board(50, 50, 1), Vec2i16 { x: 50, y: 50 });
+        assert_eq!(board(50, 50, -1), Vec2i16 { x: 80, y: 30 });
+        assert_eq!(board(25, 25, 1), Vec2i16 { x: 40, y: 20 });
+        assert_eq!(board(25, 25, -1), Vec2i16 { x: 30, y: 15 });
+    }
+
+    #[test]
+    fn game_over_shows_the_seed() {
+        use crate::game_logic::{Game, GameConfig, State, DEFAULT_RULES};
+
+        let (mut r, screen) = renderer(60, 12);
+        let config = GameConfig { board: Vec2i16 { x: 10, y: 5 }, rules: DEFAULT_RULES };
+        let mut g = Game::initialize(&mut r, Default::default(), config, 4242);
+
+        for _ in 0..20 {
+            g.update(&mut r);
+        }
+        r.render();
+
+        assert!(g.state == State::GameOver);
+        assert!(screen.borrow().get_text().contains(" game over, seed 4242, "));
+    }
+
+    #[test]
+    fn held_game_still_pauses_and_quits() {
+        use crate::game_logic::{Game, GameConfig, State, DEFAULT_RULES};
+        use crate::term_input::{Action, ActionQueue};
+
+        let (mut r, screen) = renderer(60, 5);
+        let input: ActionQueue = Default::default();
+        let config = GameConfig { board: Vec2i16 { x: 40, y: 20 }, rules: DEFAULT_RULES };
+        let mut g = Game::initialize(&mut r, input.clone(), config, 1);
+        g.update_hud(&mut r, "play");
+        g.fit_to_screen(&mut r);
+        r.render();
+        assert!(screen.borrow().get_text().contains("terminal too small, need 42x23"));
+
+        input.lock().unwrap().extend([Action::MoveUp, Action::Pause]);
+        g.update(&mut r);
+        assert!(g.state == State::Paused);
+        g.update(&mut r);
+        assert!(g.state == State::Paused);
+
+        input.lock().unwrap().push_back(Action::Pause);
+        g.update(&mut r);
+        g.update(&mut r);
+        assert!(g.state == State::Running && g.get_tick() == 0);
+
+        input.lock().unwrap().push_back(Action::Quit);
+        g.update(&mut r);
+        assert!(g.state == State::Quit);
+    }
+
+    #[test]
+    fn held_half_block_game_asks_for_terminal_rows() {
+        use crate::game_logic::{Game, GameConfig, DEFAULT_RULES};
+        use crate::term_steady_out::PixelMode;
+
+        let (mut r, screen) = renderer(60, 5);
+        r.set_pixel_mode(PixelMode::HalfBlock);
+        let config = GameConfig { board: Vec2i16 { x: 40, y: 20 }, rules: DEFAULT_RULES };
+        let mut g = Game::initialize(&mut r, Default::default(), config, 1);
+        g.update_hud(&mut r, "play");
+        g.fit_to_screen(&mut r);
+        r.render();
+
+        assert!(screen.borrow().get_text().contains("terminal too small, need 42x12"));
+    }
+}